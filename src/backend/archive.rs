@@ -0,0 +1,95 @@
+/// A minimal zip writer that stores files uncompressed, which is all an EPUB
+/// container needs.
+pub struct ZipWriter {
+    data: Vec<u8>,
+    central_directory: Vec<u8>,
+    entries: u16,
+}
+
+/// 1980-01-01 00:00, the earliest date zip can hold. A fixed timestamp keeps
+/// the archive the same from run to run.
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = 1 << 5 | 1;
+
+impl ZipWriter {
+    pub fn new() -> ZipWriter {
+        ZipWriter {
+            data: Vec::new(),
+            central_directory: Vec::new(),
+            entries: 0,
+        }
+    }
+
+    pub fn add_file(&mut self, name: &str, contents: &[u8]) {
+        let offset = self.data.len() as u32;
+        let crc = crc32(contents);
+        let size = contents.len() as u32;
+
+        self.data.extend(0x04034b50u32.to_le_bytes());
+        self.data.extend(20u16.to_le_bytes()); // version needed to extract
+        self.data.extend(0u16.to_le_bytes()); // flags
+        self.data.extend(0u16.to_le_bytes()); // stored
+        self.data.extend(DOS_TIME.to_le_bytes());
+        self.data.extend(DOS_DATE.to_le_bytes());
+        self.data.extend(crc.to_le_bytes());
+        self.data.extend(size.to_le_bytes());
+        self.data.extend(size.to_le_bytes());
+        self.data.extend((name.len() as u16).to_le_bytes());
+        self.data.extend(0u16.to_le_bytes()); // extra field length
+        self.data.extend(name.as_bytes());
+        self.data.extend(contents);
+
+        self.central_directory.extend(0x02014b50u32.to_le_bytes());
+        self.central_directory.extend(20u16.to_le_bytes()); // version made by
+        self.central_directory.extend(20u16.to_le_bytes()); // version needed to extract
+        self.central_directory.extend(0u16.to_le_bytes()); // flags
+        self.central_directory.extend(0u16.to_le_bytes()); // stored
+        self.central_directory.extend(DOS_TIME.to_le_bytes());
+        self.central_directory.extend(DOS_DATE.to_le_bytes());
+        self.central_directory.extend(crc.to_le_bytes());
+        self.central_directory.extend(size.to_le_bytes());
+        self.central_directory.extend(size.to_le_bytes());
+        self.central_directory
+            .extend((name.len() as u16).to_le_bytes());
+        self.central_directory.extend(0u16.to_le_bytes()); // extra field length
+        self.central_directory.extend(0u16.to_le_bytes()); // comment length
+        self.central_directory.extend(0u16.to_le_bytes()); // disk number
+        self.central_directory.extend(0u16.to_le_bytes()); // internal attributes
+        self.central_directory.extend(0u32.to_le_bytes()); // external attributes
+        self.central_directory.extend(offset.to_le_bytes());
+        self.central_directory.extend(name.as_bytes());
+
+        self.entries += 1;
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let offset = self.data.len() as u32;
+        let size = self.central_directory.len() as u32;
+        self.data.extend(&self.central_directory);
+
+        self.data.extend(0x06054b50u32.to_le_bytes());
+        self.data.extend(0u16.to_le_bytes()); // this disk
+        self.data.extend(0u16.to_le_bytes()); // disk with the central directory
+        self.data.extend(self.entries.to_le_bytes());
+        self.data.extend(self.entries.to_le_bytes());
+        self.data.extend(size.to_le_bytes());
+        self.data.extend(offset.to_le_bytes());
+        self.data.extend(0u16.to_le_bytes()); // comment length
+        self.data
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,405 @@
+use std::collections::HashSet;
+
+use super::{cover_file, report_broken_link, Backend, Document, Images, OutputFile};
+use crate::{html_color, Metadata, Page, PageText, Sprite};
+
+/// Beamer slides, one frame per page, compiled with any LaTeX engine.
+pub struct Beamer {
+    pub theme: String,
+    pub theme_options: Vec<String>,
+}
+
+impl Backend for Beamer {
+    fn render(&self, document: &Document) -> Result<Vec<OutputFile>, String> {
+        let cover = cover_file(&document.metadata)?;
+        let latex = latex_output(
+            &document.pages,
+            &document.metadata,
+            &document.images,
+            cover.as_ref().map(|cover| cover.path.as_str()),
+            &self.theme,
+            &self.theme_options,
+        );
+        let mut files = vec![OutputFile {
+            path: "out.tex".to_string(),
+            contents: latex.into_bytes(),
+        }];
+        files.extend(cover);
+        if self.theme == "rentex" {
+            files.push(OutputFile {
+                path: "beamerthemerentex.sty".to_string(),
+                contents: RENTEX_THEME.as_bytes().to_vec(),
+            });
+        }
+        Ok(files)
+    }
+}
+
+/// The Beamer theme shipped with rentex. Other themes used with `--theme`
+/// need to provide the same `rentextextbox` environment.
+const RENTEX_THEME: &str = include_str!("beamerthemerentex.sty");
+
+fn latex_output(
+    pages: &[Page],
+    metadata: &Metadata,
+    images: &Images,
+    cover: Option<&str>,
+    theme: &str,
+    theme_options: &[String],
+) -> String {
+    let mut output = String::new();
+
+    output += "\\documentclass[aspectratio=169]{beamer}\n\
+    \\usepackage[T1]{fontenc}\n\
+    \\usepackage{hyperref}\n";
+    if theme_options.is_empty() {
+        output += format!("\\usetheme{{{}}}\n", theme).as_str();
+    } else {
+        output += format!("\\usetheme[{}]{{{}}}\n", theme_options.join(","), theme).as_str();
+    }
+    output += format!(
+        "\\title{{{}}}\n",
+        escape_for_latex(metadata.title().to_string())
+    )
+    .as_str();
+    if let Some(version) = &metadata.version {
+        output += format!(
+            "\\subtitle{{Version {}}}\n",
+            escape_for_latex(version.clone())
+        )
+        .as_str();
+    }
+    output += format!(
+        "\\author{{{}}}\n",
+        escape_for_latex(metadata.author().to_string())
+    )
+    .as_str();
+    match &metadata.date {
+        Some(date) => output += format!("\\date{{{}}}\n", escape_for_latex(date.clone())).as_str(),
+        None => output += "\\date{\\today}\n",
+    }
+    output += "\\begin{document}\n";
+    match cover {
+        Some(cover) => {
+            output += format!(
+                "{{\\setbeamertemplate{{background}}{{\\includegraphics[width=\\paperwidth, height=\\paperheight]{{{}}}}}\n\\frame{{\\titlepage}}\n}}\n",
+                cover
+            )
+            .as_str();
+        }
+        None => output += "\\frame{\\titlepage}\n",
+    }
+
+    // Every \hypertarget the frames below will emit, so links can be checked
+    // before they are written.
+    let mut anchors: HashSet<String> = pages
+        .iter()
+        .flat_map(|page| page.targets().cloned())
+        .collect();
+    anchors.extend((0..pages.len()).map(|index| format!("pagenumber{}", index)));
+
+    for (index, page_iter) in pages.iter().enumerate() {
+        let page = page_iter.clone();
+        output += "{\n";
+        if let Some(filename) = &page.stage.background {
+            if images.exists(filename) {
+                output += format!("\\setbeamertemplate{{background}}{{\\includegraphics[width=\\paperwidth, height=\\paperheight]{{{}}}}}", images.link(filename)).as_str();
+            }
+        }
+        output += "\\begin{frame}\n";
+        let label_add: String = page
+            .targets()
+            .map(|label| format!("\\phantomsection\\hypertarget{{{}}}\n", label))
+            .collect();
+        let page_index_label = format!("\\phantomsection\\hypertarget{{pagenumber{}}}\n", index);
+        output += sprites_overlay(&page.stage.sprites, images).as_str();
+        let (character, text, choices) = match page.text.clone() {
+            PageText::Dialogue { character, text } => (character, text, None),
+            PageText::Menu {
+                character,
+                text,
+                choices,
+            } => (character, text, Some(choices)),
+        };
+        let (name, color) = match &character {
+            Some(character) => (
+                escape_for_latex(character.name.clone()),
+                html_color(&character.color).unwrap_or_default(),
+            ),
+            None => ("".to_string(), "".to_string()),
+        };
+        output += &label_add;
+        output += &page_index_label;
+        output += format!("\\begin{{rentextextbox}}{{{}}}{{{}}}\n", name, color).as_str();
+        output += format!("{}\n", escape_for_latex(text)).as_str();
+        if let Some(choices) = choices {
+            output += "\\begin{itemize}\n";
+            for choice in choices {
+                let mut text = escape_for_latex(choice.text.clone());
+                if let Some(condition) = &choice.condition {
+                    // Typewriter type, where < and > print as themselves.
+                    text += format!(
+                        " {{\\footnotesize (if \\texttt{{{}}})}}",
+                        escape_for_latex(condition.clone())
+                    )
+                    .as_str();
+                }
+                if anchors.contains(&choice.jump_key) {
+                    output +=
+                        format!("\\item \\hyperlink{{{}}}{{{}}}\n", choice.jump_key, text).as_str();
+                } else {
+                    report_broken_link(
+                        page.line,
+                        &format!("choice `{}`", choice.text),
+                        &choice.jump_key,
+                    );
+                    output += format!("\\item \\textcolor{{gray}}{{{}}}\n", text).as_str();
+                }
+            }
+            output += "\\end{itemize}\n";
+        }
+        output += "\\vfill{}\n";
+        output += "\\begin{flushright}\n";
+        let next = if let Some(jump) = page.unconditional_jump {
+            Some(jump)
+        } else if !(matches!(page.text, PageText::Menu { .. }) || page.end) {
+            Some(format!("pagenumber{}", index + 1))
+        } else {
+            None
+        };
+        if let Some(next) = next {
+            if anchors.contains(&next) {
+                output +=
+                    format!("\\hyperlink{{{}}}{{\\beamergotobutton{{Next}}}}\n", next).as_str();
+            } else {
+                report_broken_link(page.line, "Next button", &next);
+                output += DISABLED_NEXT_BUTTON;
+            }
+        }
+        output += "\\end{flushright}\n";
+        output += "\\end{rentextextbox}\n";
+        output += "\\end{frame}\n";
+        output += "}\n";
+    }
+
+    output += "\\end{document}\n";
+
+    output
+}
+
+/// A greyed-out Next button with no link, for when there is nowhere to go.
+const DISABLED_NEXT_BUTTON: &str =
+    "{\\setbeamercolor{button}{bg=gray!20, fg=gray}\\beamergotobutton{Next}}\n";
+
+/// Draws the sprites over the frame, bottom-aligned at their horizontal
+/// positions, with higher zorders on top. Nothing is written when none of
+/// them has an image, so frames without sprites don't need TikZ.
+fn sprites_overlay(sprites: &[Sprite], images: &Images) -> String {
+    let mut sprites: Vec<&Sprite> = sprites
+        .iter()
+        .filter(|sprite| images.exists(&sprite.image))
+        .collect();
+    if sprites.is_empty() {
+        return String::new();
+    }
+    sprites.sort_by_key(|sprite| sprite.zorder);
+
+    let mut output = "\\begin{tikzpicture}[remember picture, overlay]\n".to_string();
+    for sprite in sprites {
+        // Ren'Py puts the sprite's xanchor at xpos, so the left edge lands
+        // at xpos - xanchor * width.
+        let node = match images.sprite_width(sprite) {
+            Some(width) => {
+                format!(
+                    "\\node[anchor=south west, inner sep=0] at ([xshift={:.4}\\paperwidth]current page.south west) {{\\includegraphics[width={:.4}\\paperwidth]{{{}}}}};\n",
+                    sprite.left(width),
+                    width,
+                    images.link(&sprite.image)
+                )
+            }
+            // Without the width, use the nearest anchor TikZ has.
+            None => format!(
+                "\\node[anchor={}, inner sep=0] at ([xshift={:.4}\\paperwidth]current page.south west) {{\\includegraphics[height={:.4}\\paperheight]{{{}}}}};\n",
+                match sprite.xanchor {
+                    xanchor if xanchor < 0.25 => "south west",
+                    xanchor if xanchor > 0.75 => "south east",
+                    _ => "south",
+                },
+                sprite.xpos,
+                0.9 * sprite.zoom,
+                images.link(&sprite.image)
+            ),
+        };
+        output += node.as_str();
+    }
+    output += "\\end{tikzpicture}\n";
+    output
+}
+
+/// Escapes the ten characters LaTeX treats as special, and the ones the
+/// default fonts draw as other glyphs. Empty text becomes a tie, so the box
+/// it goes in keeps its height.
+fn escape_for_latex(text: String) -> String {
+    if text.is_empty() {
+        return "~".to_string();
+    }
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped += "\\textbackslash{}",
+            '~' => escaped += "\\textasciitilde{}",
+            '^' => escaped += "\\textasciicircum{}",
+            '<' => escaped += "\\textless{}",
+            '>' => escaped += "\\textgreater{}",
+            '|' => escaped += "\\textbar{}",
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transform, Character, MenuChoice, Stage};
+
+    fn dialogue_page(character: Option<Character>, text: &str) -> Page {
+        Page {
+            line: 1,
+            labels: vec!["start".to_string()],
+            anchors: Vec::new(),
+            text: PageText::Dialogue {
+                character,
+                text: text.to_string(),
+            },
+            stage: Stage::default(),
+            unconditional_jump: None,
+            end: false,
+        }
+    }
+
+    fn document(pages: Vec<Page>) -> Document {
+        Document {
+            source: "script.rpy".to_string(),
+            metadata: Metadata::default(),
+            images: Images::new("images", ".", transform::SCREEN_WIDTH),
+            lines: Vec::new(),
+            pages,
+        }
+    }
+
+    #[test]
+    fn draws_the_textbox_with_a_name_plate() {
+        let eileen = Character {
+            name: "Eileen".to_string(),
+            color: "#c8ffc8".to_string(),
+        };
+        let beamer = Beamer {
+            theme: "rentex".to_string(),
+            theme_options: vec!["opacity=0.5".to_string()],
+        };
+        let files = beamer
+            .render(&document(vec![dialogue_page(Some(eileen), "Hi.")]))
+            .unwrap();
+        let latex = String::from_utf8(files[0].contents.clone()).unwrap();
+        assert!(latex.contains("\\usetheme[opacity=0.5]{rentex}\n"));
+        assert!(latex.contains("\\begin{rentextextbox}{Eileen}{C8FFC8}\nHi.\n"));
+        assert!(files
+            .iter()
+            .any(|file| file.path == "beamerthemerentex.sty"));
+
+        let beamer = Beamer {
+            theme: "custom".to_string(),
+            theme_options: Vec::new(),
+        };
+        let files = beamer.render(&document(Vec::new())).unwrap();
+        assert!(!files
+            .iter()
+            .any(|file| file.path == "beamerthemerentex.sty"));
+    }
+
+    #[test]
+    fn leaves_out_the_overlay_without_sprite_images() {
+        let mut page = dialogue_page(None, "Hi.");
+        page.stage.sprites.push(Sprite {
+            image: "missing".to_string(),
+            xpos: 0.5,
+            xanchor: 0.5,
+            zorder: 0,
+            zoom: 1.0,
+        });
+        let images = Images::new("images", ".", transform::SCREEN_WIDTH);
+        let latex = latex_output(&[page], &Metadata::default(), &images, None, "rentex", &[]);
+        assert!(!latex.contains("tikzpicture"));
+    }
+
+    #[test]
+    fn writes_the_title_page_from_the_metadata() {
+        let metadata = Metadata {
+            title: Some("The Question".to_string()),
+            version: Some("1.0".to_string()),
+            date: Some("2024".to_string()),
+            ..Metadata::default()
+        };
+        let images = Images::new("images", ".", transform::SCREEN_WIDTH);
+        let latex = latex_output(&[], &metadata, &images, None, "rentex", &[]);
+        assert!(latex.contains("\\usetheme{rentex}\n"));
+        assert!(latex.contains(
+            "\\title{The Question}\n\\subtitle{Version 1.0}\n\\author{Game Author}\n\\date{2024}\n"
+        ));
+        assert!(latex.contains("\\frame{\\titlepage}\n"));
+
+        let latex = latex_output(&[], &metadata, &images, Some("cover.png"), "rentex", &[]);
+        assert!(latex
+            .contains("\\includegraphics[width=\\paperwidth, height=\\paperheight]{cover.png}"));
+    }
+
+    #[test]
+    fn draws_broken_links_disabled() {
+        let choice = |text: &str, jump_key: &str| MenuChoice {
+            text: text.to_string(),
+            jump_key: jump_key.to_string(),
+            condition: None,
+        };
+        let menu = Page {
+            text: PageText::Menu {
+                character: None,
+                text: String::new(),
+                choices: vec![choice("Back", "start"), choice("Away", "nowhere")],
+            },
+            ..dialogue_page(None, "")
+        };
+        let last = Page {
+            labels: Vec::new(),
+            ..dialogue_page(None, "Bye.")
+        };
+        let beamer = Beamer {
+            theme: "rentex".to_string(),
+            theme_options: Vec::new(),
+        };
+        let files = beamer.render(&document(vec![menu, last])).unwrap();
+        let latex = String::from_utf8(files[0].contents.clone()).unwrap();
+        assert!(latex.contains("\\item \\hyperlink{start}{Back}\n"));
+        assert!(latex.contains("\\item \\textcolor{gray}{Away}\n"));
+        assert!(!latex.contains("\\hyperlink{nowhere}"));
+        assert!(!latex.contains("\\hyperlink{pagenumber2}"));
+        assert!(latex.contains(DISABLED_NEXT_BUTTON));
+    }
+
+    #[test]
+    fn escapes_special_characters() {
+        assert_eq!(
+            escape_for_latex("50% & $5 #1 a_b {x} \\ ~ ^".to_string()),
+            "50\\% \\& \\$5 \\#1 a\\_b \\{x\\} \\textbackslash{} \\textasciitilde{} \\textasciicircum{}"
+        );
+        assert_eq!(
+            escape_for_latex("<a|b>".to_string()),
+            "\\textless{}a\\textbar{}b\\textgreater{}"
+        );
+        assert_eq!(escape_for_latex(String::new()), "~");
+    }
+}
@@ -0,0 +1,141 @@
+use super::{Backend, Document, OutputFile};
+use crate::graph::{EdgeKind, FlowGraph, NodeKind, Target};
+
+/// The route structure as a Graphviz graph, for `dot -Tsvg out.dot`.
+/// Labels nobody can reach from `start` are greyed out and dead ends are
+/// drawn in red.
+pub struct Dot;
+
+impl Backend for Dot {
+    fn uses_pages(&self) -> bool {
+        false
+    }
+
+    fn render(&self, document: &Document) -> Result<Vec<OutputFile>, String> {
+        Ok(vec![OutputFile {
+            path: "out.dot".to_string(),
+            contents: dot_output(&FlowGraph::build(&document.lines)).into_bytes(),
+        }])
+    }
+}
+
+fn dot_output(graph: &FlowGraph) -> String {
+    let mut output = "digraph story {\n".to_string();
+    output += "    node [shape=box, fontname=\"sans-serif\"];\n";
+    output += "    edge [fontname=\"sans-serif\", fontsize=10];\n";
+
+    for (index, node) in graph.nodes.iter().enumerate() {
+        let mut attributes = match &node.kind {
+            NodeKind::Label { key } => vec![format!("label=\"{}\"", escape_for_dot(key))],
+            NodeKind::Menu { name } => vec![
+                format!(
+                    "label=\"menu{}\\nline {}\"",
+                    name.as_ref()
+                        .map_or("".to_string(), |name| format!(" {}", escape_for_dot(name))),
+                    node.line
+                ),
+                "shape=diamond".to_string(),
+            ],
+            NodeKind::AfterMenu => vec![
+                "label=\"\"".to_string(),
+                "shape=circle".to_string(),
+                "width=0.15".to_string(),
+            ],
+            NodeKind::End => vec![
+                "label=\"END\"".to_string(),
+                "shape=doublecircle".to_string(),
+            ],
+        };
+        if graph.is_dead_end(index) {
+            attributes.push("color=red".to_string());
+            attributes.push("penwidth=2".to_string());
+        }
+        if !node.reachable {
+            attributes.push("style=\"filled,dashed\"".to_string());
+            attributes.push("fillcolor=lightgrey".to_string());
+            attributes.push("fontcolor=gray40".to_string());
+        }
+        output += format!("    n{} [{}];\n", index, attributes.join(", ")).as_str();
+    }
+
+    let mut missing = Vec::<&str>::new();
+    for edge in &graph.edges {
+        let to = match &edge.to {
+            Target::Node(node) => format!("n{}", node),
+            Target::Missing(key) => {
+                let position = missing.iter().position(|other| other == key);
+                let index = position.unwrap_or_else(|| {
+                    missing.push(key);
+                    missing.len() - 1
+                });
+                format!("missing{}", index)
+            }
+        };
+        let mut attributes = match &edge.kind {
+            EdgeKind::FallThrough => vec!["style=dotted".to_string()],
+            EdgeKind::Jump | EdgeKind::Return => vec![],
+            EdgeKind::Choice { text, condition } => match condition {
+                Some(condition) => vec![format!(
+                    "label=\"{}\\n(if {})\"",
+                    escape_for_dot(text),
+                    escape_for_dot(condition)
+                )],
+                None => vec![format!("label=\"{}\"", escape_for_dot(text))],
+            },
+            EdgeKind::Call => vec!["label=\"call\"".to_string(), "style=dashed".to_string()],
+        };
+        attributes.push(format!(
+            "tooltip=\"from line {}, dialogue lines: {}\"",
+            edge.line,
+            edge.dialogue.len()
+        ));
+        output += format!(
+            "    n{} -> {} [{}];\n",
+            edge.from,
+            to,
+            attributes.join(", ")
+        )
+        .as_str();
+    }
+
+    // Jumps to labels that are never defined get a placeholder node so the
+    // broken edge stays visible.
+    for (index, key) in missing.iter().enumerate() {
+        output += format!(
+            "    missing{} [label=\"{}\\n(undefined)\", style=dashed, color=red, fontcolor=red];\n",
+            index,
+            escape_for_dot(key)
+        )
+        .as_str();
+    }
+
+    output += "}\n";
+    output
+}
+
+fn escape_for_dot(text: &str) -> String {
+    text.replace("\\", "\\\\")
+        .replace("\"", "\\\"")
+        .replace("\n", "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlights_unreachable_labels_dead_ends_and_missing_targets() {
+        let lines = crate::parse_test_script(
+            "label start:\n    menu:\n        \"Stay\":\n            jump start\n        \"Go \\\"on\\\"\":\n            jump nowhere\nlabel lost:\n    \"Alone.\"\n",
+        );
+        let output = dot_output(&FlowGraph::build(&lines));
+        assert!(output.contains("    n0 [label=\"start\"];\n"));
+        assert!(output.contains(
+            "    n1 [label=\"lost\", color=red, penwidth=2, style=\"filled,dashed\", fillcolor=lightgrey, fontcolor=gray40];\n"
+        ));
+        assert!(output.contains("    n0 -> n2 [style=dotted, "));
+        assert!(output.contains("    n2 -> n0 [label=\"Stay\", "));
+        assert!(output.contains("    n2 -> missing0 [label=\"Go \\\"on\\\"\", "));
+        assert!(output.contains("    missing0 [label=\"nowhere\\n(undefined)\", "));
+    }
+}
@@ -0,0 +1,557 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+
+use super::archive::ZipWriter;
+use super::{cover_file, escape_for_html, stable_uuid, Backend, Document, Images, OutputFile};
+use crate::{html_color, Metadata, Page, PageText, Sprite};
+
+/// An EPUB 3 book for e-readers, with one chapter per label and menu
+/// choices as links between chapters. Scene and sprite changes are shown as
+/// images inline with the text, since reflowable books can't layer them.
+pub struct Epub;
+
+impl Backend for Epub {
+    fn render(&self, document: &Document) -> Result<Vec<OutputFile>, String> {
+        Ok(vec![OutputFile {
+            path: "out.epub".to_string(),
+            contents: epub_output(document)?,
+        }])
+    }
+}
+
+const STYLE: &str = "\
+body { font-family: serif; line-height: 1.4; }
+img.background { display: block; width: 100%; margin: 1em 0; }
+div.sprites { text-align: center; margin: 1em 0; }
+div.sprites img { height: 8em; margin: 0 0.5em; vertical-align: bottom; }
+.speaker { font-weight: bold; }
+ol.choices { margin: 1em 0; }
+span.condition { font-size: 0.85em; font-style: italic; }
+section.cover { text-align: center; }
+section.cover img { display: block; width: 100%; margin-bottom: 1em; }
+p.next, p.end { text-align: right; font-style: italic; }
+";
+
+/// The pages from one label up to the next.
+struct Chapter<'a> {
+    label: String,
+    /// The chapter's file, without `.xhtml`.
+    file: String,
+    pages: Vec<&'a Page>,
+    /// For a label sharing its page with others, the label whose chapter
+    /// holds the pages. Its own chapter only links there.
+    alias_of: Option<String>,
+}
+
+impl<'a> Chapter<'a> {
+    fn new(label: &str, alias_of: Option<&String>) -> Chapter<'a> {
+        Chapter {
+            label: label.to_string(),
+            file: file_name(label),
+            pages: Vec::new(),
+            alias_of: alias_of.cloned(),
+        }
+    }
+}
+
+fn epub_output(document: &Document) -> Result<Vec<u8>, String> {
+    let mut chapters: Vec<Chapter> = Vec::new();
+    for page in &document.pages {
+        // The aliases come just before the chapter they lead to.
+        if let Some((label, aliases)) = page.labels.split_first() {
+            for alias in aliases {
+                chapters.push(Chapter::new(alias, Some(label)));
+            }
+            chapters.push(Chapter::new(label, None));
+        }
+        // The first page carries `label start`, unless traversal changes.
+        if chapters.is_empty() {
+            chapters.push(Chapter::new("start", None));
+        }
+        chapters.last_mut().unwrap().pages.push(page);
+    }
+    // Every label and anchor to where it leads: the chapter a label opens,
+    // or the page inside a chapter.
+    let mut labels = HashMap::<&str, String>::new();
+    for chapter in &chapters {
+        labels.insert(&chapter.label, format!("{}.xhtml", chapter.file));
+    }
+    for chapter in &chapters {
+        for page in &chapter.pages {
+            for target in page.targets() {
+                labels.entry(target).or_insert(format!(
+                    "{}.xhtml#{}",
+                    chapter.file,
+                    escape_for_url(target)
+                ));
+            }
+        }
+    }
+
+    let mut images = BTreeSet::new();
+    let mut zip = ZipWriter::new();
+    // The mimetype has to be the first entry, uncompressed.
+    zip.add_file("mimetype", b"application/epub+zip");
+    zip.add_file(
+        "META-INF/container.xml",
+        b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+<rootfiles>\n\
+<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n\
+</rootfiles>\n\
+</container>\n",
+    );
+    zip.add_file("OEBPS/style.css", STYLE.as_bytes());
+    // The cover page's name can't clash with a chapter, since chapter files
+    // only have a hyphen before two hex digits.
+    let cover = cover_file(&document.metadata)?;
+    if let Some(cover) = &cover {
+        zip.add_file(format!("OEBPS/{}", cover.path).as_str(), &cover.contents);
+        zip.add_file(
+            "OEBPS/cover-page.xhtml",
+            cover_xhtml(&document.metadata, &cover.path).as_bytes(),
+        );
+    }
+    for chapter in &chapters {
+        zip.add_file(
+            format!("OEBPS/{}.xhtml", chapter.file).as_str(),
+            chapter_xhtml(chapter, &labels, &document.images, &mut images).as_bytes(),
+        );
+    }
+    zip.add_file("OEBPS/nav.xhtml", nav_xhtml(&chapters).as_bytes());
+    for image in &images {
+        let path = document.images.path(image, "png");
+        let contents = fs::read(&path).map_err(|error| format!("{}: {}", path, error))?;
+        zip.add_file(format!("OEBPS/{}", image_href(image)).as_str(), &contents);
+    }
+    zip.add_file(
+        "OEBPS/content.opf",
+        package_opf(
+            &document.source,
+            &document.metadata,
+            cover.as_ref().map(|cover| cover.path.as_str()),
+            &chapters,
+            &images,
+        )
+        .as_bytes(),
+    );
+
+    Ok(zip.finish())
+}
+
+fn xhtml_head(title: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+<!DOCTYPE html>\n\
+<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" lang=\"en\" xml:lang=\"en\">\n\
+<head>\n<meta charset=\"utf-8\"/>\n<title>{}</title>\n\
+<link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/>\n</head>\n<body>\n",
+        escape_for_html(title)
+    )
+}
+
+/// Writes a chapter, adding the images it shows to `images`. The scene and
+/// the sprites are shown at the start and again whenever they change.
+fn chapter_xhtml(
+    chapter: &Chapter,
+    labels: &HashMap<&str, String>,
+    image_files: &Images,
+    images: &mut BTreeSet<String>,
+) -> String {
+    let mut output = xhtml_head(&chapter.label);
+    output += format!(
+        "<section epub:type=\"chapter\" id=\"{}\">\n<h1>{}</h1>\n",
+        escape_for_html(&chapter.label),
+        escape_for_html(&chapter.label)
+    )
+    .as_str();
+
+    let mut background = None;
+    let mut sprites: Option<&[Sprite]> = None;
+    for page in &chapter.pages {
+        if page.stage.background != background {
+            background = page.stage.background.clone();
+            if let Some(image) = background
+                .as_ref()
+                .filter(|image| image_files.exists(image))
+            {
+                images.insert(image.clone());
+                output += format!(
+                    "<img class=\"background\" src=\"{}\" alt=\"{}\"/>\n",
+                    image_href(image),
+                    escape_for_html(image)
+                )
+                .as_str();
+            }
+        }
+        if sprites != Some(page.stage.sprites.as_slice()) {
+            sprites = Some(page.stage.sprites.as_slice());
+            let mut shown: Vec<&Sprite> = page
+                .stage
+                .sprites
+                .iter()
+                .filter(|sprite| image_files.exists(&sprite.image))
+                .collect();
+            shown.sort_by(|a, b| a.xpos.total_cmp(&b.xpos));
+            if !shown.is_empty() {
+                output += "<div class=\"sprites\">";
+                for sprite in shown {
+                    images.insert(sprite.image.clone());
+                    output += format!(
+                        "<img src=\"{}\" alt=\"{}\"/>",
+                        image_href(&sprite.image),
+                        escape_for_html(&sprite.image)
+                    )
+                    .as_str();
+                }
+                output += "</div>\n";
+            }
+        }
+
+        for target in page.targets() {
+            if !page.labels.contains(target) {
+                output += format!("<a id=\"{}\"></a>\n", escape_for_html(target)).as_str();
+            }
+        }
+        if page.end {
+            output += "<p class=\"end\">THE END</p>\n";
+            continue;
+        }
+        let (character, text, choices) = match &page.text {
+            PageText::Dialogue { character, text } => (character, text, None),
+            PageText::Menu {
+                character,
+                text,
+                choices,
+            } => (character, text, Some(choices)),
+        };
+        if !text.is_empty() {
+            output += "<p>";
+            if let Some(character) = character {
+                let style = match html_color(&character.color) {
+                    Some(color) => format!(" style=\"color: #{}\"", color),
+                    None => "".to_string(),
+                };
+                output += format!(
+                    "<span class=\"speaker\"{}>{}</span> ",
+                    style,
+                    escape_for_html(&character.name)
+                )
+                .as_str();
+            }
+            output += format!("{}</p>\n", escape_for_html(text).replace("\n", "<br/>")).as_str();
+        }
+        if let Some(choices) = choices {
+            output += "<ol class=\"choices\">\n";
+            for choice in choices {
+                output += format!(
+                    "<li>{}{}</li>\n",
+                    chapter_link(&choice.jump_key, &choice.text, labels),
+                    choice
+                        .condition
+                        .as_ref()
+                        .map_or("".to_string(), |condition| format!(
+                            " <span class=\"condition\">(if {})</span>",
+                            escape_for_html(condition)
+                        ))
+                )
+                .as_str();
+            }
+            output += "</ol>\n";
+        }
+        if let Some(jump) = &page.unconditional_jump {
+            output += format!(
+                "<p class=\"next\">{}</p>\n",
+                chapter_link(jump, "Continue", labels)
+            )
+            .as_str();
+        }
+    }
+    if let Some(label) = &chapter.alias_of {
+        output += format!(
+            "<p class=\"next\">{}</p>\n",
+            chapter_link(label, "Continue", labels)
+        )
+        .as_str();
+    }
+
+    output += "</section>\n</body>\n</html>\n";
+    output
+}
+
+/// A link to where `label` leads, or plain text when the label has no page
+/// to go to.
+fn chapter_link(label: &str, text: &str, labels: &HashMap<&str, String>) -> String {
+    match labels.get(label) {
+        Some(href) => format!("<a href=\"{}\">{}</a>", href, escape_for_html(text)),
+        None => escape_for_html(text),
+    }
+}
+
+/// The cover image with the title, version, author and date under it.
+fn cover_xhtml(metadata: &Metadata, cover: &str) -> String {
+    let mut output = xhtml_head(metadata.title());
+    output += "<section epub:type=\"cover\" class=\"cover\">\n";
+    output += format!("<img src=\"{}\" alt=\"\"/>\n", cover).as_str();
+    output += format!("<h1>{}</h1>\n", escape_for_html(metadata.title())).as_str();
+    if let Some(version) = &metadata.version {
+        output += format!("<p>Version {}</p>\n", escape_for_html(version)).as_str();
+    }
+    output += format!("<p>{}</p>\n", escape_for_html(metadata.author())).as_str();
+    if let Some(date) = &metadata.date {
+        output += format!("<p>{}</p>\n", escape_for_html(date)).as_str();
+    }
+    output += "</section>\n</body>\n</html>\n";
+    output
+}
+
+fn nav_xhtml(chapters: &[Chapter]) -> String {
+    let mut output = xhtml_head("Contents");
+    output += "<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n";
+    for chapter in chapters {
+        output += format!(
+            "<li><a href=\"{}.xhtml\">{}</a></li>\n",
+            chapter.file,
+            escape_for_html(&chapter.label)
+        )
+        .as_str();
+    }
+    output += "</ol>\n</nav>\n</body>\n</html>\n";
+    output
+}
+
+fn package_opf(
+    source: &str,
+    metadata: &Metadata,
+    cover: Option<&str>,
+    chapters: &[Chapter],
+    images: &BTreeSet<String>,
+) -> String {
+    let mut output = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"uid\" xml:lang=\"en\">\n\
+<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n"
+        .to_string();
+    output += format!(
+        "<dc:identifier id=\"uid\">urn:uuid:{}</dc:identifier>\n",
+        stable_uuid(source).to_lowercase()
+    )
+    .as_str();
+    output += format!(
+        "<dc:title>{}</dc:title>\n<dc:creator>{}</dc:creator>\n",
+        escape_for_html(metadata.title()),
+        escape_for_html(metadata.author())
+    )
+    .as_str();
+    // Free-form dates such as "Spring 2024" aren't valid here.
+    if let Some(date) = metadata
+        .date
+        .as_ref()
+        .filter(|date| w3c_date(date).is_some())
+    {
+        output += format!("<dc:date>{}</dc:date>\n", escape_for_html(date)).as_str();
+    }
+    output += "<dc:language>en</dc:language>\n";
+    output += format!(
+        "<meta property=\"dcterms:modified\">{}</meta>\n",
+        modified_timestamp(metadata)
+    )
+    .as_str();
+    output += "</metadata>\n<manifest>\n";
+    output += "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n";
+    output += "<item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n";
+    for (index, chapter) in chapters.iter().enumerate() {
+        output += format!(
+            "<item id=\"chapter{}\" href=\"{}.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+            index, chapter.file
+        )
+        .as_str();
+    }
+    for (index, image) in images.iter().enumerate() {
+        output += format!(
+            "<item id=\"image{}\" href=\"{}\" media-type=\"image/png\"/>\n",
+            index,
+            image_href(image)
+        )
+        .as_str();
+    }
+    if let Some(cover) = cover {
+        let media_type = if cover.ends_with(".png") {
+            "image/png"
+        } else {
+            "image/jpeg"
+        };
+        output += format!(
+            "<item id=\"cover-image\" href=\"{}\" media-type=\"{}\" properties=\"cover-image\"/>\n",
+            cover, media_type
+        )
+        .as_str();
+        output += "<item id=\"cover-page\" href=\"cover-page.xhtml\" media-type=\"application/xhtml+xml\"/>\n";
+    }
+    output += "</manifest>\n<spine>\n";
+    if cover.is_some() {
+        output += "<itemref idref=\"cover-page\"/>\n";
+    }
+    for index in 0..chapters.len() {
+        output += format!("<itemref idref=\"chapter{}\"/>\n", index).as_str();
+    }
+    output += "</spine>\n</package>\n";
+    output
+}
+
+/// Splits a W3C date, as `dc:date` takes it, into its year, month and day:
+/// `2024`, `2024-05`, `2024-05-17` or a date with a time such as
+/// `2024-05-17T09:30:00Z`. Returns `None` for anything else.
+fn w3c_date(text: &str) -> Option<(u32, u32, u32)> {
+    let (date, time) = match text.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+    let parts: Vec<&str> = date.split('-').collect();
+    let digits = |part: &str, length: usize| {
+        (part.len() == length && part.bytes().all(|byte| byte.is_ascii_digit()))
+            .then(|| part.parse::<u32>().ok())
+            .flatten()
+    };
+    let (year, month, day) = match parts[..] {
+        [year] => (digits(year, 4)?, 1, 1),
+        [year, month] => (digits(year, 4)?, digits(month, 2)?, 1),
+        [year, month, day] => (digits(year, 4)?, digits(month, 2)?, digits(day, 2)?),
+        _ => return None,
+    };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if !(1..=days).contains(&day) {
+        return None;
+    }
+    if let Some(time) = time {
+        // A time needs the full date, and is hh:mm, optionally with seconds
+        // and fractions, then `Z` or an offset.
+        let (clock, zone) = match time.strip_suffix('Z') {
+            Some(clock) => (clock, None),
+            None => {
+                let sign = time.rfind(['+', '-'])?;
+                (&time[..sign], Some(&time[sign + 1..]))
+            }
+        };
+        let (clock, fraction) = clock.split_once('.').unwrap_or((clock, "0"));
+        let is_time = |text: &str, parts: &[usize]| {
+            let fields: Vec<&str> = text.split(':').collect();
+            parts.contains(&fields.len())
+                && fields
+                    .iter()
+                    .zip([24, 60, 60])
+                    .all(|(field, limit)| digits(field, 2).is_some_and(|value| value < limit))
+        };
+        if parts.len() != 3
+            || !is_time(clock, &[2, 3])
+            || fraction.is_empty()
+            || !fraction.bytes().all(|byte| byte.is_ascii_digit())
+            || zone.is_some_and(|zone| !is_time(zone, &[2]))
+        {
+            return None;
+        }
+    }
+    Some((year, month, day))
+}
+
+/// `dcterms:modified` for the book: midnight on the game's date, or the
+/// same fixed date the zip entries carry when there is none, so the same
+/// script always gives the same file.
+fn modified_timestamp(metadata: &Metadata) -> String {
+    let (year, month, day) = metadata
+        .date
+        .as_deref()
+        .and_then(w3c_date)
+        .unwrap_or((1980, 1, 1));
+    format!("{:04}-{:02}-{:02}T00:00:00Z", year, month, day)
+}
+
+fn image_href(image: &str) -> String {
+    format!("images/{}.png", file_name(image))
+}
+
+/// A file name for `text` that needs no escaping in zip entries or hrefs.
+/// ASCII letters, digits and underscores are kept and every other byte is
+/// written as `-` and two hex digits, so different names stay apart.
+fn file_name(text: &str) -> String {
+    let mut name = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'_' {
+            name.push(byte as char);
+        } else {
+            name += format!("-{:02X}", byte).as_str();
+        }
+    }
+    name
+}
+
+fn escape_for_url(text: &str) -> String {
+    let mut escaped = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            escaped.push(byte as char);
+        } else {
+            escaped += format!("%{:02X}", byte).as_str();
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform;
+
+    #[test]
+    fn gives_every_label_its_own_file() {
+        let lines = crate::parse_test_script(
+            "label start:\n    jump intro.part\nlabel intro.part:\n    \"Hi.\"\n    return\n",
+        );
+        let document = Document {
+            source: "script.rpy".to_string(),
+            metadata: Metadata::default(),
+            images: Images::new("images", ".", transform::SCREEN_WIDTH),
+            pages: crate::traverse_game(lines.clone()).unwrap(),
+            lines,
+        };
+        let epub = epub_output(&document).unwrap();
+        let epub = String::from_utf8_lossy(&epub);
+        // The same name in the zip and in the links to it.
+        assert!(epub.contains("OEBPS/start.xhtml"));
+        assert!(epub.contains("OEBPS/intro-2Epart.xhtml"));
+        assert!(epub.contains("href=\"intro-2Epart.xhtml\""));
+        assert!(!epub.contains("intro.part.xhtml"));
+        // `start` only jumps on, so its chapter links to the one with the
+        // pages.
+        assert!(epub.contains("<p class=\"next\"><a href=\"intro-2Epart.xhtml\">Continue</a></p>"));
+    }
+
+    #[test]
+    fn reads_w3c_dates() {
+        assert_eq!(w3c_date("2024"), Some((2024, 1, 1)));
+        assert_eq!(w3c_date("2024-02"), Some((2024, 2, 1)));
+        assert_eq!(w3c_date("2024-02-29"), Some((2024, 2, 29)));
+        assert_eq!(w3c_date("2024-02-29T10:30Z"), Some((2024, 2, 29)));
+        assert_eq!(w3c_date("2024-02-29T10:30:15.5+01:00"), Some((2024, 2, 29)));
+        assert_eq!(w3c_date("2023-02-29"), None);
+        assert_eq!(w3c_date("2024-13"), None);
+        assert_eq!(w3c_date("24-01-01"), None);
+        assert_eq!(w3c_date("2024-01T10:30Z"), None);
+        assert_eq!(w3c_date("2024-01-01T25:00Z"), None);
+        assert_eq!(w3c_date("spring 2024"), None);
+    }
+
+    #[test]
+    fn dates_the_book_reproducibly() {
+        let mut metadata = Metadata::default();
+        assert_eq!(modified_timestamp(&metadata), "1980-01-01T00:00:00Z");
+        metadata.date = Some("2024-05-06".to_string());
+        assert_eq!(modified_timestamp(&metadata), "2024-05-06T00:00:00Z");
+    }
+}
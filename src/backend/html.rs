@@ -0,0 +1,284 @@
+use std::collections::HashSet;
+
+use super::{
+    cover_file, escape_for_html, report_broken_link, Backend, Document, Images, OutputFile,
+};
+use crate::{html_color, Metadata, Page, PageText, Sprite};
+
+/// A single HTML file that plays through the script with plain links, so
+/// it needs no JavaScript, network access or LaTeX install.
+pub struct Html;
+
+impl Backend for Html {
+    fn render(&self, document: &Document) -> Result<Vec<OutputFile>, String> {
+        let cover = cover_file(&document.metadata)?;
+        let html = html_output(
+            &document.pages,
+            &document.metadata,
+            &document.images,
+            cover.as_ref().map(|cover| cover.path.as_str()),
+        );
+        let mut files = vec![OutputFile {
+            path: "out.html".to_string(),
+            contents: html.into_bytes(),
+        }];
+        files.extend(cover);
+        Ok(files)
+    }
+}
+
+const STYLE: &str = "\
+body { margin: 0; background: #111; font-family: sans-serif; }
+.page { min-height: 100vh; display: flex; align-items: center; justify-content: center; }
+.stage { position: relative; width: min(100vw, 177.78vh); aspect-ratio: 16 / 9; overflow: hidden;
+  background: #333 center / 100% 100% no-repeat; }
+.sprite { position: absolute; bottom: 0; }
+.textbox { position: absolute; left: 1.5%; right: 1.5%; bottom: 2%; min-height: 28%; box-sizing: border-box;
+  padding: 1.2em 1.5em; border-radius: 0.6em; background: rgba(0, 0, 0, 0.75); color: white; z-index: 1000; }
+.nameplate { position: absolute; top: -1.4em; left: 1em; padding: 0.2em 0.8em; border-radius: 0.4em;
+  background: gray; color: black; font-weight: bold; }
+.textbox a { color: #9cf; }
+.next { position: absolute; right: 1.5em; bottom: 0.8em; }
+.disabled { color: #777; }
+.condition { color: #aaa; font-size: 0.85em; }
+.titlebox { position: absolute; left: 50%; top: 50%; transform: translate(-50%, -50%); padding: 1.5em 3em;
+  border-radius: 0.6em; background: rgba(0, 0, 0, 0.75); color: white; text-align: center; }
+.titlebox a { color: #9cf; }
+";
+
+fn html_output(
+    pages: &[Page],
+    metadata: &Metadata,
+    images: &Images,
+    cover: Option<&str>,
+) -> String {
+    let mut output = String::new();
+
+    output += "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n";
+    output += format!("<title>{}</title>\n", escape_for_html(metadata.title())).as_str();
+    output += format!("<style>\n{}</style>\n", STYLE).as_str();
+    output += "</head>\n<body>\n";
+
+    if let Some(cover) = cover {
+        output += "<section class=\"page\" id=\"cover\">\n";
+        output += format!(
+            "<div class=\"stage\" style=\"background-image: url('{}')\">\n",
+            cover
+        )
+        .as_str();
+        output += "<div class=\"titlebox\">\n";
+        output += format!("<h1>{}</h1>\n", escape_for_html(metadata.title())).as_str();
+        if let Some(version) = &metadata.version {
+            output += format!("<p>Version {}</p>\n", escape_for_html(version)).as_str();
+        }
+        output += format!("<p>{}</p>\n", escape_for_html(metadata.author())).as_str();
+        if let Some(date) = &metadata.date {
+            output += format!("<p>{}</p>\n", escape_for_html(date)).as_str();
+        }
+        output += "<p><a href=\"#pagenumber0\">Start</a></p>\n";
+        output += "</div>\n</div>\n</section>\n";
+    }
+
+    // Every id the pages below will have, so links can be checked.
+    let mut targets: HashSet<String> = pages
+        .iter()
+        .flat_map(|page| page.targets().cloned())
+        .collect();
+    targets.extend((0..pages.len()).map(|index| format!("pagenumber{}", index)));
+
+    for (index, page) in pages.iter().enumerate() {
+        output += format!("<section class=\"page\" id=\"pagenumber{}\">\n", index).as_str();
+        for label in page.targets() {
+            output += format!("<a id=\"{}\"></a>\n", escape_for_html(label)).as_str();
+        }
+        match &page.stage.background {
+            Some(filename) if images.exists(filename) => {
+                output += format!(
+                    "<div class=\"stage\" style=\"background-image: url('{}')\">\n",
+                    escape_for_html(&images.link(filename))
+                )
+                .as_str();
+            }
+            _ => output += "<div class=\"stage\">\n",
+        }
+        output += sprite_images(&page.stage.sprites, images).as_str();
+
+        let (character, text, choices) = match &page.text {
+            PageText::Dialogue { character, text } => (character, text, None),
+            PageText::Menu {
+                character,
+                text,
+                choices,
+            } => (character, text, Some(choices)),
+        };
+        output += "<div class=\"textbox\">\n";
+        if let Some(character) = character {
+            let style = match html_color(&character.color) {
+                Some(color) => format!(" style=\"background: #{}\"", color),
+                None => "".to_string(),
+            };
+            output += format!(
+                "<div class=\"nameplate\"{}>{}</div>\n",
+                style,
+                escape_for_html(&character.name)
+            )
+            .as_str();
+        }
+        output += format!("<p>{}</p>\n", escape_for_html(text).replace("\n", "<br>")).as_str();
+        if let Some(choices) = choices {
+            output += "<ul class=\"choices\">\n";
+            for choice in choices {
+                let link = if targets.contains(&choice.jump_key) {
+                    format!(
+                        "<a href=\"#{}\">{}</a>",
+                        escape_for_html(&choice.jump_key),
+                        escape_for_html(&choice.text)
+                    )
+                } else {
+                    report_broken_link(
+                        page.line,
+                        &format!("choice `{}`", choice.text),
+                        &choice.jump_key,
+                    );
+                    format!(
+                        "<span class=\"disabled\">{}</span>",
+                        escape_for_html(&choice.text)
+                    )
+                };
+                output += format!(
+                    "<li>{}{}</li>\n",
+                    link,
+                    choice
+                        .condition
+                        .as_ref()
+                        .map_or("".to_string(), |condition| format!(
+                            " <span class=\"condition\">(if {})</span>",
+                            escape_for_html(condition)
+                        ))
+                )
+                .as_str();
+            }
+            output += "</ul>\n";
+        }
+        let next = if let Some(jump) = &page.unconditional_jump {
+            Some(jump.clone())
+        } else if !(matches!(page.text, PageText::Menu { .. }) || page.end) {
+            Some(format!("pagenumber{}", index + 1))
+        } else {
+            None
+        };
+        if let Some(next) = next {
+            if targets.contains(&next) {
+                output += format!(
+                    "<a class=\"next\" href=\"#{}\">Next</a>\n",
+                    escape_for_html(&next)
+                )
+                .as_str();
+            } else {
+                report_broken_link(page.line, "Next button", &next);
+                output += "<span class=\"next disabled\">Next</span>\n";
+            }
+        }
+        output += "</div>\n</div>\n</section>\n";
+    }
+
+    output += "</body>\n</html>\n";
+
+    output
+}
+
+/// Positions the sprites the same way the Beamer overlay does, as
+/// percentages of the stage so they scale with the window.
+fn sprite_images(sprites: &[Sprite], images: &Images) -> String {
+    let mut output = String::new();
+    for sprite in sprites {
+        if !images.exists(&sprite.image) {
+            continue;
+        }
+        let style = match images.sprite_width(sprite) {
+            Some(width) => format!(
+                "left: {:.2}%; width: {:.2}%; z-index: {}",
+                sprite.left(width) * 100.0,
+                width * 100.0,
+                sprite.zorder
+            ),
+            None => format!(
+                "left: {:.2}%; height: {:.2}%; transform: translateX({:.2}%); z-index: {}",
+                sprite.xpos * 100.0,
+                sprite.zoom * 90.0,
+                -sprite.xanchor * 100.0,
+                sprite.zorder
+            ),
+        };
+        output += format!(
+            "<img class=\"sprite\" src=\"{}\" alt=\"{}\" style=\"{}\">\n",
+            escape_for_html(&images.link(&sprite.image)),
+            escape_for_html(&sprite.image),
+            style
+        )
+        .as_str();
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transform, MenuChoice, Stage};
+
+    #[test]
+    fn draws_broken_links_disabled() {
+        let choice = |text: &str, jump_key: &str| MenuChoice {
+            text: text.to_string(),
+            jump_key: jump_key.to_string(),
+            condition: None,
+        };
+        let page = |labels: &[&str], text: PageText, unconditional_jump: Option<&str>| Page {
+            line: 1,
+            labels: labels.iter().map(|label| label.to_string()).collect(),
+            anchors: Vec::new(),
+            text,
+            stage: Stage::default(),
+            unconditional_jump: unconditional_jump.map(|jump| jump.to_string()),
+            end: false,
+        };
+        let pages = [
+            page(
+                &["start"],
+                PageText::Menu {
+                    character: None,
+                    text: String::new(),
+                    choices: vec![choice("Back", "start"), choice("Away", "nowhere")],
+                },
+                None,
+            ),
+            page(
+                &[],
+                PageText::Dialogue {
+                    character: None,
+                    text: "Lost.".to_string(),
+                },
+                Some("nowhere"),
+            ),
+            page(
+                &[],
+                PageText::Dialogue {
+                    character: None,
+                    text: "Bye.".to_string(),
+                },
+                None,
+            ),
+        ];
+        let images = Images::new("images", ".", transform::SCREEN_WIDTH);
+        let html = html_output(&pages, &Metadata::default(), &images, None);
+        assert!(html.contains("<li><a href=\"#start\">Back</a></li>\n"));
+        assert!(html.contains("<li><span class=\"disabled\">Away</span></li>\n"));
+        assert!(!html.contains("href=\"#nowhere\""));
+        assert!(!html.contains("href=\"#pagenumber3\""));
+        assert_eq!(
+            html.matches("<span class=\"next disabled\">Next</span>\n")
+                .count(),
+            2
+        );
+    }
+}
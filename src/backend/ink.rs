@@ -0,0 +1,354 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    block_end, character_names, label_bodies, speaker_name, Backend, Document, OutputFile,
+};
+use crate::{is_identifier, take_string_literal, Metadata, ParseLogicalLine, ParseStatement};
+
+/// An Ink story: labels become knots, menus become choices, named menus
+/// become labelled gathers and jumps become diverts, so the writing can be
+/// reused in an Ink runtime.
+pub struct Ink;
+
+impl Backend for Ink {
+    fn uses_pages(&self) -> bool {
+        false
+    }
+
+    fn render(&self, document: &Document) -> Result<Vec<OutputFile>, String> {
+        Ok(vec![OutputFile {
+            path: "out.ink".to_string(),
+            contents: ink_output(&document.lines, &document.metadata).into_bytes(),
+        }])
+    }
+}
+
+struct KnotWriter<'a> {
+    names: HashMap<&'a str, &'a str>,
+    /// Labels used with `call`, whose `return` goes back to the caller.
+    called: HashSet<&'a str>,
+    /// Whether the knot being written is one of them.
+    tunnel: bool,
+    /// Named menus, which become labelled gathers, to the knot they are in.
+    menus: HashMap<&'a str, &'a str>,
+    /// Labels, which become knots.
+    labels: HashSet<&'a str>,
+}
+
+fn ink_output(lines: &[ParseLogicalLine], metadata: &Metadata) -> String {
+    let mut writer = KnotWriter {
+        names: character_names(lines),
+        called: HashSet::new(),
+        tunnel: false,
+        menus: HashMap::new(),
+        labels: HashSet::new(),
+    };
+    let mut output = String::new();
+    // Global tags, which Inky and most runtimes read the story's title and
+    // author from.
+    if let Some(title) = &metadata.title {
+        output += format!("# title: {}\n", title).as_str();
+    }
+    if let Some(author) = &metadata.author {
+        output += format!("# author: {}\n", author).as_str();
+    }
+    for line in lines {
+        match &line.statement {
+            ParseStatement::Call { key } => {
+                writer.called.insert(key);
+            }
+            ParseStatement::Default { name, value } => {
+                output += match ink_value(value).filter(|_| is_identifier(name)) {
+                    Some(value) => format!("VAR {} = {}\n", name, value),
+                    None => format!("// default {} = {}\n", name, value),
+                }
+                .as_str();
+            }
+            _ => (),
+        }
+    }
+    if !output.is_empty() {
+        output += "\n";
+    }
+
+    let labels = label_bodies(lines);
+    for (label, body) in &labels {
+        writer.labels.insert(label);
+        for line in body.iter() {
+            if let ParseStatement::Menu { name: Some(name) } = &line.statement {
+                writer.menus.insert(name, label);
+            }
+        }
+    }
+    if writer.labels.contains("start") {
+        output += "-> start\n";
+    } else {
+        output += "// no `label start` to begin at\n-> END\n";
+    }
+    for (number, (label, body)) in labels.iter().enumerate() {
+        output += format!("\n=== {} ===\n", ink_name(label)).as_str();
+        writer.tunnel = writer.called.contains(label);
+        if writer.render_block(body, 0, &mut output) {
+            match labels.get(number + 1) {
+                Some((next, _)) => output += format!("-> {}\n", ink_name(next)).as_str(),
+                None => output += "-> END\n",
+            }
+        }
+    }
+
+    output
+}
+
+impl KnotWriter<'_> {
+    /// The divert target for a label or named menu, or `None` if there is
+    /// neither by that name, which Ink won't compile a divert to.
+    fn target(&self, key: &str) -> Option<String> {
+        match self.menus.get(key) {
+            Some(knot) => Some(format!("{}.{}", ink_name(knot), ink_name(key))),
+            None if self.labels.contains(key) => Some(ink_name(key)),
+            None => None,
+        }
+    }
+
+    /// Writes `lines` at choice nesting `depth`, returning whether play can
+    /// run off the end.
+    fn render_block(&self, lines: &[ParseLogicalLine], depth: usize, output: &mut String) -> bool {
+        let indent = "    ".repeat(depth);
+        let mut index = 0;
+        while index < lines.len() {
+            match &lines[index].statement {
+                ParseStatement::Dialogue { speaker, text } => {
+                    let name = speaker_name(speaker, &self.names);
+                    for (number, text) in text.lines().enumerate() {
+                        if let Some(name) = name.filter(|_| number == 0) {
+                            *output += format!(
+                                "{}{}: {}\n",
+                                indent,
+                                escape_for_ink(name),
+                                escape_for_ink(text)
+                            )
+                            .as_str();
+                        } else {
+                            *output += format!("{}{}\n", indent, escape_for_ink(text)).as_str();
+                        }
+                    }
+                }
+                ParseStatement::Jump { key } => {
+                    match self.target(key) {
+                        Some(target) => *output += format!("{}-> {}\n", indent, target).as_str(),
+                        None => {
+                            *output += format!(
+                                "{}// jump to undefined label `{}`\n{}-> END\n",
+                                indent, key, indent
+                            )
+                            .as_str()
+                        }
+                    }
+                    return false;
+                }
+                ParseStatement::Call { key } => match self.target(key) {
+                    Some(target) => *output += format!("{}-> {} ->\n", indent, target).as_str(),
+                    None => {
+                        *output +=
+                            format!("{}// call to undefined label `{}`\n", indent, key).as_str()
+                    }
+                },
+                ParseStatement::End {} => {
+                    if self.tunnel {
+                        *output += format!("{}->->\n", indent).as_str();
+                    } else {
+                        *output += format!("{}-> END\n", indent).as_str();
+                    }
+                    return false;
+                }
+                ParseStatement::Menu { name } => {
+                    let end = block_end(lines, index);
+                    if let Some(name) = name {
+                        *output +=
+                            format!("{}{} ({})\n", indent, "-".repeat(depth + 1), ink_name(name))
+                                .as_str();
+                    }
+                    if !self.render_menu(&lines[index + 1..end], depth, output) {
+                        return false;
+                    }
+                    index = end;
+                    continue;
+                }
+                _ => (),
+            }
+            index += 1;
+        }
+        true
+    }
+
+    /// Writes a menu as its caption and choices, and a gather for the
+    /// choices that don't divert away. Returns whether any choice gets there.
+    fn render_menu(&self, body: &[ParseLogicalLine], depth: usize, output: &mut String) -> bool {
+        let indent = "    ".repeat(depth);
+        let first_choice = body
+            .iter()
+            .position(|line| matches!(line.statement, ParseStatement::Choice { .. }))
+            .unwrap_or(body.len());
+        self.render_block(&body[..first_choice], depth, output);
+
+        let mut falls_through = false;
+        let mut index = first_choice;
+        while index < body.len() {
+            let end = block_end(body, index);
+            if let ParseStatement::Choice { text, condition } = &body[index].statement {
+                let block = &body[index + 1..end];
+                if let Some(condition) = condition {
+                    *output += format!("{}// if {}\n", indent, condition).as_str();
+                }
+                *output += format!(
+                    "{}{} [{}]",
+                    indent,
+                    "*".repeat(depth + 1),
+                    escape_for_ink(text)
+                )
+                .as_str();
+                let divert = match block {
+                    [ParseLogicalLine {
+                        statement: ParseStatement::Jump { key },
+                        ..
+                    }] => self.target(key),
+                    _ => None,
+                };
+                match divert {
+                    Some(target) => *output += format!(" -> {}\n", target).as_str(),
+                    None => {
+                        *output += "\n";
+                        falls_through |= self.render_block(block, depth + 1, output);
+                    }
+                }
+            }
+            index = end;
+        }
+
+        if falls_through {
+            *output += format!("{}{}\n", indent, "-".repeat(depth + 1)).as_str();
+        }
+        falls_through
+    }
+}
+
+/// Turns a label or menu name into an Ink identifier, the same way for the
+/// knot or gather and every divert to it. The dots of local labels become
+/// double underscores, anything else Ink doesn't allow an underscore, and a
+/// leading digit gets an underscore in front.
+fn ink_name(label: &str) -> String {
+    let mut name = String::new();
+    if label.starts_with(|c: char| c.is_ascii_digit()) {
+        name.push('_');
+    }
+    for c in label.chars() {
+        match c {
+            '.' => name += "__",
+            c if c.is_ascii_alphanumeric() || c == '_' => name.push(c),
+            _ => name.push('_'),
+        }
+    }
+    name
+}
+
+/// Converts a Ren'Py `default` value to an Ink `VAR` value, for the literals
+/// Ink has.
+fn ink_value(value: &str) -> Option<String> {
+    let value = value.trim();
+    match value {
+        "True" => Some("true".to_string()),
+        "False" => Some("false".to_string()),
+        _ if value.parse::<f64>().is_ok_and(|number| number.is_finite()) => Some(value.to_string()),
+        _ => {
+            let (text, rest) = take_string_literal(value)?;
+            rest.is_empty()
+                .then(|| format!("\"{}\"", escape_quotes(&text)))
+        }
+    }
+}
+
+/// Escapes the double quotes in the raw contents of a Ren'Py string literal
+/// that aren't escaped already, which only a single-quoted one can have.
+fn escape_quotes(text: &str) -> String {
+    let mut escaped = String::new();
+    let mut after_backslash = false;
+    for c in text.chars() {
+        if c == '"' && !after_backslash {
+            escaped.push('\\');
+        }
+        after_backslash = c == '\\' && !after_backslash;
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escapes the characters Ink would read as markup: brackets, braces,
+/// alternatives, tags, comments, diverts and glue, plus choice and gather
+/// marks at the start of a line.
+fn escape_for_ink(text: &str) -> String {
+    let mut escaped = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next = chars.peek().copied();
+        let special = match c {
+            '\\' | '[' | ']' | '{' | '}' | '|' | '#' => true,
+            '/' => matches!(next, Some('/' | '*')),
+            '-' => next == Some('>') || escaped.trim().is_empty(),
+            '<' => next == Some('>'),
+            '*' | '+' | '=' | '~' => escaped.trim().is_empty(),
+            _ => false,
+        };
+        if special {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ink(script: &str) -> String {
+        let lines = crate::parse_test_script(script);
+        ink_output(&lines, &Metadata::default())
+    }
+
+    #[test]
+    fn keeps_escaped_quotes_in_values() {
+        assert_eq!(
+            ink_value(r#""say \"hi\"""#).as_deref(),
+            Some(r#""say \"hi\"""#)
+        );
+        assert_eq!(
+            ink_value(r#"'say "hi"'"#).as_deref(),
+            Some(r#""say \"hi\"""#)
+        );
+        assert_eq!(ink_value("'a\\\\'").as_deref(), Some("\"a\\\\\""));
+    }
+
+    #[test]
+    fn ends_at_undefined_labels() {
+        let output = ink(
+            "label start:\n    menu pick:\n        \"Again\":\n            jump pick\n        \"Leave\":\n            jump nowhere\n",
+        );
+        assert!(output.contains("* [Again] -> start.pick\n"));
+        assert!(
+            output.contains("* [Leave]\n    // jump to undefined label `nowhere`\n    -> END\n")
+        );
+        assert!(!output.contains("-> nowhere"));
+    }
+
+    #[test]
+    fn turns_labels_into_ink_names() {
+        let output = ink(
+            "label chapter1:\n    jump chapter1.end\nlabel chapter1.end:\n    \"A\"\n    jump 2nd\nlabel 2nd:\n    \"B\"\n",
+        );
+        assert!(output.starts_with("// no `label start` to begin at\n-> END\n"));
+        assert!(!output.contains("-> start"));
+        assert!(output.contains("=== chapter1 ===\n-> chapter1__end\n"));
+        assert!(output.contains("=== chapter1__end ===\nA\n-> _2nd\n"));
+        assert!(output.contains("=== _2nd ===\n"));
+    }
+}
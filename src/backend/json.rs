@@ -0,0 +1,131 @@
+use serde::Serialize;
+
+use super::{Backend, Document, OutputFile};
+use crate::{Metadata, Page, ParseLogicalLine};
+
+/// Bumped whenever the shape of the JSON output changes, so tools reading it
+/// can tell which layout they were given.
+const FORMAT_VERSION: u32 = 6;
+
+/// The parsed statements and the traversed page graph as JSON, for tools
+/// that consume scripts as data.
+pub struct Json;
+
+impl Backend for Json {
+    fn render(&self, document: &Document) -> Result<Vec<OutputFile>, String> {
+        let output = JsonDocument {
+            format: "rentex",
+            version: FORMAT_VERSION,
+            source: &document.source,
+            metadata: &document.metadata,
+            statements: document
+                .lines
+                .iter()
+                .map(|line| JsonStatement {
+                    location: Location {
+                        file: &document.source,
+                        line: line.line,
+                    },
+                    line,
+                })
+                .collect(),
+            pages: document
+                .pages
+                .iter()
+                .enumerate()
+                .map(|(number, page)| JsonPage {
+                    number,
+                    location: Location {
+                        file: &document.source,
+                        line: page.line,
+                    },
+                    page,
+                })
+                .collect(),
+        };
+        let json = serde_json::to_string_pretty(&output).map_err(|error| error.to_string())?;
+        Ok(vec![OutputFile {
+            path: "out.json".to_string(),
+            contents: (json + "\n").into_bytes(),
+        }])
+    }
+}
+
+#[derive(Serialize)]
+struct JsonDocument<'a> {
+    format: &'static str,
+    version: u32,
+    source: &'a str,
+    metadata: &'a Metadata,
+    statements: Vec<JsonStatement<'a>>,
+    pages: Vec<JsonPage<'a>>,
+}
+
+#[derive(Serialize)]
+struct Location<'a> {
+    file: &'a str,
+    line: usize,
+}
+
+#[derive(Serialize)]
+struct JsonStatement<'a> {
+    location: Location<'a>,
+    #[serde(flatten)]
+    line: &'a ParseLogicalLine,
+}
+
+/// A page with its position in the page list, which is also the number used
+/// by `pagenumber` anchors and Next links.
+#[derive(Serialize)]
+struct JsonPage<'a> {
+    number: usize,
+    location: Location<'a>,
+    #[serde(flatten)]
+    page: &'a Page,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Images;
+    use crate::transform;
+
+    #[test]
+    fn writes_statements_and_pages_with_locations() {
+        let lines = crate::parse_test_script(
+            "define e = Character(\"Eileen\")\nlabel start:\n    e \"Hi.\"\n    return\n",
+        );
+        let document = Document {
+            source: "script.rpy".to_string(),
+            metadata: Metadata::default(),
+            images: Images::new("images", ".", transform::SCREEN_WIDTH),
+            pages: crate::traverse_game(lines.clone()).unwrap(),
+            lines,
+        };
+        let files = Json.render(&document).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&files[0].contents).unwrap();
+
+        assert_eq!(json["format"], "rentex");
+        assert_eq!(json["version"], FORMAT_VERSION);
+        assert_eq!(json["source"], "script.rpy");
+        assert_eq!(
+            json["statements"][2],
+            serde_json::json!({
+                "location": { "file": "script.rpy", "line": 3 },
+                "indent": 4,
+                "statement": {
+                    "type": "dialogue",
+                    "speaker": { "kind": "character", "name": "e" },
+                    "text": "Hi.",
+                },
+            })
+        );
+        let page = &json["pages"][0];
+        assert_eq!(page["number"], 0);
+        assert_eq!(page["location"]["line"], 3);
+        assert_eq!(page["labels"], serde_json::json!(["start"]));
+        assert_eq!(page["text"]["type"], "dialogue");
+        assert_eq!(page["text"]["character"]["name"], "Eileen");
+        assert_eq!(json["pages"][1]["end"], true);
+    }
+}
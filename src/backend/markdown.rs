@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+
+use super::{block_end, character_names, speaker_name, Backend, Document, OutputFile};
+use crate::{Metadata, ParseLogicalLine, ParseStatement};
+
+/// A linear, screenplay-style script for proofreading and diffs, written
+/// straight from the parsed statements in file order.
+pub struct Markdown;
+
+impl Backend for Markdown {
+    fn uses_pages(&self) -> bool {
+        false
+    }
+
+    fn render(&self, document: &Document) -> Result<Vec<OutputFile>, String> {
+        Ok(vec![OutputFile {
+            path: "out.md".to_string(),
+            contents: markdown_output(&document.lines, &document.metadata).into_bytes(),
+        }])
+    }
+}
+
+fn markdown_output(lines: &[ParseLogicalLine], metadata: &Metadata) -> String {
+    let mut output = format!("# {}\n\n", escape_for_markdown(metadata.title()));
+    let byline: Vec<String> = [
+        metadata.author.clone(),
+        metadata
+            .version
+            .as_ref()
+            .map(|version| format!("version {}", version)),
+        metadata.date.clone(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !byline.is_empty() {
+        output += format!("*{}*\n\n", escape_for_markdown(&byline.join(", "))).as_str();
+    }
+
+    let mut cast = String::new();
+    for line in lines {
+        if let ParseStatement::Definition { key, character } = &line.statement {
+            cast += format!(
+                "- **{}** (`{}`)\n",
+                escape_for_markdown(&character.name),
+                key
+            )
+            .as_str();
+        }
+    }
+    if !cast.is_empty() {
+        output += format!("## Characters\n\n{}\n", cast).as_str();
+    }
+
+    render_block(lines, &character_names(lines), &mut output);
+
+    output
+}
+
+fn render_block(lines: &[ParseLogicalLine], names: &HashMap<&str, &str>, output: &mut String) {
+    let mut index = 0;
+    while index < lines.len() {
+        match &lines[index].statement {
+            ParseStatement::Definition { .. }
+            | ParseStatement::StageDirection { .. }
+            | ParseStatement::Default { .. } => (),
+            ParseStatement::Label { key } => {
+                *output += format!("## {}\n\n", key).as_str();
+            }
+            ParseStatement::MenuSet { name } => {
+                *output +=
+                    format!("*Choices already picked are hidden (set `{}`)*\n\n", name).as_str();
+            }
+            ParseStatement::Dialogue { speaker, text } => {
+                let text = escape_for_markdown(text).replace("\n", "  \n");
+                match speaker_name(speaker, names) {
+                    Some(name) => {
+                        *output +=
+                            format!("**{}:** {}\n\n", escape_for_markdown(name), text).as_str();
+                    }
+                    None => *output += format!("{}\n\n", text).as_str(),
+                }
+            }
+            ParseStatement::Menu { name } => {
+                // Named menus are jump targets, so they get a heading like
+                // a label.
+                if let Some(name) = name {
+                    *output += format!("## {}\n\n", name).as_str();
+                }
+                let end = block_end(lines, index);
+                render_menu(&lines[index + 1..end], names, output);
+                index = end;
+                continue;
+            }
+            ParseStatement::Choice { text, condition } => {
+                *output += format!("**Choice:** {}", escape_for_markdown(text)).as_str();
+                if let Some(condition) = condition {
+                    *output += format!(" *(if `{}`)*", condition).as_str();
+                }
+                *output += "\n\n";
+            }
+            ParseStatement::Jump { key } => {
+                *output += format!("→ `{}`\n\n", key).as_str();
+            }
+            ParseStatement::Call { key } => {
+                *output += format!("↳ `{}`\n\n", key).as_str();
+            }
+            ParseStatement::End {} => {
+                *output += "**THE END**\n\n";
+            }
+            ParseStatement::Show {
+                key,
+                placement,
+                zorder,
+            } => {
+                let mut details = Vec::new();
+                if let Some(xpos) = placement.xpos {
+                    details.push(format!("xpos {:.2}", xpos));
+                }
+                if let Some(xanchor) = placement.xanchor {
+                    details.push(format!("xanchor {:.2}", xanchor));
+                }
+                if let Some(zoom) = placement.zoom {
+                    details.push(format!("zoom {:.2}", zoom));
+                }
+                if let Some(zorder) = zorder {
+                    details.push(format!("zorder {}", zorder));
+                }
+                let details = if details.is_empty() {
+                    "".to_string()
+                } else {
+                    format!(" ({})", details.join(", "))
+                };
+                *output += format!("*Show {}{}*\n\n", escape_for_markdown(key), details).as_str();
+            }
+            ParseStatement::Hide { key } => {
+                *output += format!("*Hide {}*\n\n", escape_for_markdown(key)).as_str();
+            }
+            ParseStatement::Scene { key } => {
+                *output += format!("### {}\n\n", escape_for_markdown(&key.to_uppercase())).as_str();
+            }
+        }
+        index += 1;
+    }
+}
+
+/// Writes a menu as its caption, a numbered list of choices with the label
+/// each one jumps to, and then the lines inside any choice that does more
+/// than jump.
+fn render_menu(body: &[ParseLogicalLine], names: &HashMap<&str, &str>, output: &mut String) {
+    let first_choice = body
+        .iter()
+        .position(|line| matches!(line.statement, ParseStatement::Choice { .. }))
+        .unwrap_or(body.len());
+    render_block(&body[..first_choice], names, output);
+
+    let mut choices = Vec::new();
+    let mut index = first_choice;
+    while index < body.len() {
+        let end = block_end(body, index);
+        if let ParseStatement::Choice { text, condition } = &body[index].statement {
+            let block = &body[index + 1..end];
+            // Jumps inside a nested menu belong to its choices, not this one.
+            let indent = block.first().map(|line| line.indent);
+            let target = block
+                .iter()
+                .filter(|line| Some(line.indent) == indent)
+                .find_map(|line| match &line.statement {
+                    ParseStatement::Jump { key } => Some(key.as_str()),
+                    _ => None,
+                });
+            choices.push((text, condition, target, block));
+        }
+        index = end;
+    }
+
+    for (number, (text, condition, target, _)) in choices.iter().enumerate() {
+        *output += format!("{}. {}", number + 1, escape_for_markdown(text)).as_str();
+        if let Some(condition) = condition {
+            *output += format!(" *(if `{}`)*", condition).as_str();
+        }
+        if let Some(target) = target {
+            *output += format!(" → `{}`", target).as_str();
+        }
+        *output += "\n";
+    }
+    *output += "\n";
+
+    for (number, (text, _, _, block)) in choices.iter().enumerate() {
+        if block
+            .iter()
+            .all(|line| matches!(line.statement, ParseStatement::Jump { .. }))
+        {
+            continue;
+        }
+        *output += format!("**{}. {}**\n\n", number + 1, escape_for_markdown(text)).as_str();
+        render_block(block, names, output);
+    }
+}
+
+fn escape_for_markdown(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markdown(script: &str) -> String {
+        let lines = crate::parse_test_script(script);
+        markdown_output(&lines, &Metadata::default())
+    }
+
+    #[test]
+    fn lists_choice_targets() {
+        let output = markdown(
+            "label start:\n    menu:\n        \"Again\":\n            jump start\n        \"Wait\":\n            \"Hm.\"\n            jump other\nlabel other:\n    return\n",
+        );
+        assert!(output.contains("1. Again → `start`\n2. Wait → `other`\n\n"));
+        assert!(!output.contains("**1. Again**"));
+        assert!(output.contains("**2. Wait**\n\nHm.\n\n→ `other`\n\n"));
+    }
+
+    #[test]
+    fn named_menus_get_a_heading() {
+        let output = markdown(
+            "label start:\n    menu chapter_choice:\n        \"Again\":\n            jump chapter_choice\n",
+        );
+        assert!(output.contains("## start\n\n## chapter_choice\n\n1. Again → `chapter_choice`\n"));
+    }
+
+    #[test]
+    fn nested_menu_jumps_are_not_the_choice_target() {
+        let output = markdown(
+            "label start:\n    menu:\n        \"A\":\n            menu:\n                \"B\":\n                    pass\n                \"C\":\n                    jump start\n",
+        );
+        assert!(output.contains("1. A\n\n"));
+        assert!(output.contains("1. B\n2. C → `start`\n\n"));
+    }
+}
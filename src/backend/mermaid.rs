@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use super::{character_names, speaker_name, Backend, Document, OutputFile};
+use crate::graph::{EdgeKind, FlowGraph, NodeKind, Target};
+use crate::{ParseLogicalLine, ParseStatement};
+
+/// The route structure as a Mermaid flowchart for wikis and docs. The say
+/// statements between two nodes are drawn as a chain of boxes, or as one
+/// box with a line count when `collapse_dialogue` is set.
+pub struct Mermaid {
+    pub collapse_dialogue: bool,
+}
+
+impl Backend for Mermaid {
+    fn uses_pages(&self) -> bool {
+        false
+    }
+
+    fn render(&self, document: &Document) -> Result<Vec<OutputFile>, String> {
+        Ok(vec![OutputFile {
+            path: "out.mmd".to_string(),
+            contents: mermaid_output(&document.lines, self.collapse_dialogue).into_bytes(),
+        }])
+    }
+}
+
+/// Longest dialogue excerpt shown in an expanded box.
+const EXCERPT_LENGTH: usize = 40;
+
+fn mermaid_output(lines: &[ParseLogicalLine], collapse_dialogue: bool) -> String {
+    let graph = FlowGraph::build(lines);
+
+    let names = character_names(lines);
+
+    let mut output = "flowchart TD\n".to_string();
+    let mut unreachable = Vec::new();
+    let mut dead_ends = Vec::new();
+    for (index, node) in graph.nodes.iter().enumerate() {
+        let shape = match &node.kind {
+            NodeKind::Label { key } => format!("[\"{}\"]", escape_for_mermaid(key)),
+            NodeKind::Menu { name } => format!(
+                "{{\"menu{}<br>line {}\"}}",
+                name.as_ref().map_or("".to_string(), |name| format!(
+                    " {}",
+                    escape_for_mermaid(name)
+                )),
+                node.line
+            ),
+            NodeKind::AfterMenu => "((\" \"))".to_string(),
+            NodeKind::End => "(((\"END\")))".to_string(),
+        };
+        output += format!("    n{}{}\n", index, shape).as_str();
+        if !node.reachable {
+            unreachable.push(format!("n{}", index));
+        }
+        if graph.is_dead_end(index) {
+            dead_ends.push(format!("n{}", index));
+        }
+    }
+
+    let mut missing = Vec::<&str>::new();
+    for (number, edge) in graph.edges.iter().enumerate() {
+        let to = match &edge.to {
+            Target::Node(node) => format!("n{}", node),
+            Target::Missing(key) => {
+                let position = missing.iter().position(|other| other == key);
+                let index = position.unwrap_or_else(|| {
+                    missing.push(key);
+                    missing.len() - 1
+                });
+                format!("missing{}", index)
+            }
+        };
+        let arrow = match &edge.kind {
+            EdgeKind::FallThrough => "-.->".to_string(),
+            EdgeKind::Jump | EdgeKind::Return => "-->".to_string(),
+            EdgeKind::Choice { text, condition } => match condition {
+                Some(condition) => format!(
+                    "-->|\"{}<br>(if {})\"|",
+                    escape_for_mermaid(text),
+                    escape_for_mermaid(condition)
+                ),
+                None => format!("-->|\"{}\"|", escape_for_mermaid(text)),
+            },
+            EdgeKind::Call => "-.->|call|".to_string(),
+        };
+
+        // The chain of boxes the edge passes through on its way, after the
+        // arrow that carries the edge's own style and label.
+        let mut stops = Vec::new();
+        if collapse_dialogue && !edge.dialogue.is_empty() {
+            let count = edge.dialogue.len();
+            stops.push(format!(
+                "e{}([\"{} line{}\"])",
+                number,
+                count,
+                if count == 1 { "" } else { "s" }
+            ));
+        } else {
+            for (step, index) in edge.dialogue.iter().enumerate() {
+                stops.push(format!(
+                    "e{}s{}[\"{}\"]",
+                    number,
+                    step,
+                    escape_for_mermaid(&excerpt(&lines[*index], &names))
+                ));
+            }
+        }
+        stops.push(to);
+        output += format!("    n{} {} {}\n", edge.from, arrow, stops.join(" --> ")).as_str();
+    }
+
+    for (index, key) in missing.iter().enumerate() {
+        output += format!(
+            "    missing{}[\"{}<br>(undefined)\"]\n",
+            index,
+            escape_for_mermaid(key)
+        )
+        .as_str();
+    }
+
+    output += "    classDef unreachable fill:#eee,stroke:#999,stroke-dasharray:5 5,color:#777\n";
+    output += "    classDef deadEnd stroke:#d00,stroke-width:3px\n";
+    output += "    classDef missing fill:#fee,stroke:#d00,stroke-dasharray:5 5,color:#d00\n";
+    if !unreachable.is_empty() {
+        output += format!("    class {} unreachable\n", unreachable.join(",")).as_str();
+    }
+    if !dead_ends.is_empty() {
+        output += format!("    class {} deadEnd\n", dead_ends.join(",")).as_str();
+    }
+    if !missing.is_empty() {
+        let nodes: Vec<String> = (0..missing.len())
+            .map(|index| format!("missing{}", index))
+            .collect();
+        output += format!("    class {} missing\n", nodes.join(",")).as_str();
+    }
+
+    output
+}
+
+/// A say statement as `Name: text`, cut short to fit in a box.
+fn excerpt(line: &ParseLogicalLine, names: &HashMap<&str, &str>) -> String {
+    let ParseStatement::Dialogue { speaker, text } = &line.statement else {
+        return "".to_string();
+    };
+    let text = text.replace("\n", " ");
+    let text = if text.chars().count() > EXCERPT_LENGTH {
+        format!(
+            "{}…",
+            text.chars()
+                .take(EXCERPT_LENGTH)
+                .collect::<String>()
+                .trim_end()
+        )
+    } else {
+        text
+    };
+    match speaker_name(speaker, names) {
+        Some(name) => format!("{}: {}", name, text),
+        None => text,
+    }
+}
+
+/// Mermaid reads `#...;` as an entity inside quoted labels, so quotes and
+/// angle brackets are written that way.
+fn escape_for_mermaid(text: &str) -> String {
+    text.replace("#", "#35;")
+        .replace("\"", "#quot;")
+        .replace("<", "#lt;")
+        .replace(">", "#gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "define e = Character(\"Eileen\")\nlabel start:\n    e \"Hi.\"\n    \"It was raining.\"\n    jump end\nlabel end:\n    return\n";
+
+    #[test]
+    fn draws_dialogue_as_a_chain_of_boxes() {
+        let output = mermaid_output(&crate::parse_test_script(SCRIPT), false);
+        assert!(output
+            .contains("    n0 --> e0s0[\"Eileen: Hi.\"] --> e0s1[\"It was raining.\"] --> n1\n"));
+    }
+
+    #[test]
+    fn collapses_dialogue_to_a_line_count() {
+        let output = mermaid_output(&crate::parse_test_script(SCRIPT), true);
+        assert!(output.contains("    n0 --> e0([\"2 lines\"]) --> n1\n"));
+        assert!(!output.contains("Hi."));
+    }
+}
@@ -0,0 +1,352 @@
+mod archive;
+mod beamer;
+mod dot;
+mod epub;
+mod html;
+mod ink;
+mod json;
+mod markdown;
+mod mermaid;
+#[cfg(feature = "pdf")]
+mod pdf;
+mod twee;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{self, Component};
+
+use crate::{Metadata, Options, Page, ParseLogicalLine, ParseStatement, Speaker, Sprite};
+
+pub use twee::STORY_FORMAT_NAMES;
+
+/// Everything a backend can render: the parsed script and the pages
+/// traversal produced from it.
+pub struct Document {
+    /// Path of the script the document was parsed from.
+    pub source: String,
+    pub metadata: Metadata,
+    pub images: Images,
+    pub lines: Vec<ParseLogicalLine>,
+    /// Empty when the backend doesn't use pages.
+    pub pages: Vec<Page>,
+}
+
+/// Where the backgrounds and sprites are, as PNGs named after the image,
+/// and the size of the screen they were drawn for.
+pub struct Images {
+    /// Directory the images are read from.
+    pub dir: String,
+    /// The same directory as output files link to it, relative to the
+    /// output directory.
+    pub link: String,
+    /// Width in pixels that sprite sizes are measured against.
+    pub screen_width: f32,
+}
+
+impl Images {
+    pub fn new(dir: &str, output: &str, screen_width: f32) -> Images {
+        Images {
+            dir: dir.to_string(),
+            link: relative_path(output, dir),
+            screen_width,
+        }
+    }
+
+    /// Path of the file for image `name`, e.g. `output/images/bg room.png`.
+    pub fn path(&self, name: &str, extension: &str) -> String {
+        format!("{}/{}.{}", self.dir, name, extension)
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        fs::exists(self.path(name, "png")).unwrap_or(false)
+    }
+
+    /// How output files refer to the PNG for image `name`.
+    pub fn link(&self, name: &str) -> String {
+        format!("{}/{}.png", self.link, name)
+    }
+
+    /// Width of a sprite as a fraction of the screen, from its PNG size and
+    /// zoom, when the image can be read.
+    pub fn sprite_width(&self, sprite: &Sprite) -> Option<f32> {
+        let (width, _) = png_size(&self.path(&sprite.image, "png"))?;
+        Some(width as f32 / self.screen_width * sprite.zoom)
+    }
+}
+
+/// `to` as a relative path from the directory `from`, for links from files
+/// in one to files in the other.
+fn relative_path(from: &str, to: &str) -> String {
+    let components = |path: &str| {
+        let mut components = Vec::new();
+        for component in path::absolute(path).unwrap_or_default().components() {
+            match component {
+                Component::CurDir => (),
+                Component::ParentDir => {
+                    components.pop();
+                }
+                _ => components.push(component.as_os_str().to_string_lossy().to_string()),
+            }
+        }
+        components
+    };
+    let from = components(from);
+    let to = components(to);
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut parts = vec!["..".to_string(); from.len() - common];
+    parts.extend(to[common..].iter().cloned());
+    if parts.is_empty() {
+        ".".to_string()
+    } else {
+        parts.join("/")
+    }
+}
+
+/// A file produced by a backend, with its path relative to the output
+/// directory.
+pub struct OutputFile {
+    pub path: String,
+    pub contents: Vec<u8>,
+}
+
+/// An output format. Backends only read the document, so adding one never
+/// touches parsing or traversal.
+pub trait Backend {
+    /// Whether the backend reads `Document::pages`. Backends that only
+    /// read the lines can export scripts that can't be traversed, such as
+    /// chapters without `label start`.
+    fn uses_pages(&self) -> bool {
+        true
+    }
+
+    fn render(&self, document: &Document) -> Result<Vec<OutputFile>, String>;
+}
+
+/// Names accepted by `--backend`.
+pub const BACKEND_NAMES: &[&str] = &[
+    "beamer",
+    "dot",
+    "epub",
+    "html",
+    "ink",
+    "json",
+    "markdown",
+    "mermaid",
+    #[cfg(feature = "pdf")]
+    "pdf",
+    "twee",
+];
+
+pub fn backend_by_name(name: &str, options: &Options) -> Option<Box<dyn Backend>> {
+    match name {
+        "beamer" => Some(Box::new(beamer::Beamer {
+            theme: options.theme.clone(),
+            theme_options: options.theme_options.clone(),
+        })),
+        "dot" => Some(Box::new(dot::Dot)),
+        "epub" => Some(Box::new(epub::Epub)),
+        "html" => Some(Box::new(html::Html)),
+        "ink" => Some(Box::new(ink::Ink)),
+        "json" => Some(Box::new(json::Json)),
+        "markdown" => Some(Box::new(markdown::Markdown)),
+        "mermaid" => Some(Box::new(mermaid::Mermaid {
+            collapse_dialogue: options.collapse_dialogue,
+        })),
+        #[cfg(feature = "pdf")]
+        "pdf" => Some(Box::new(pdf::Pdf {
+            font: options.font.clone(),
+        })),
+        "twee" => Some(Box::new(twee::Twee {
+            format: twee::StoryFormat::by_name(&options.twine_format)?,
+        })),
+        _ => None,
+    }
+}
+
+/// The cover image as a file to write next to the output, `cover.png` or
+/// `cover.jpg`, when the document has a cover page.
+pub fn cover_file(metadata: &Metadata) -> Result<Option<OutputFile>, String> {
+    let Some(cover) = &metadata.cover else {
+        return Ok(None);
+    };
+    let contents = fs::read(cover).map_err(|error| format!("{}: {}", cover, error))?;
+    let extension = if cover.to_lowercase().ends_with(".png") {
+        "png"
+    } else {
+        "jpg"
+    };
+    Ok(Some(OutputFile {
+        path: format!("cover.{}", extension),
+        contents,
+    }))
+}
+
+/// Reads the pixel size out of a PNG header.
+fn png_size(path: &str) -> Option<(u32, u32)> {
+    let header = fs::read(path).ok()?;
+    if header.len() < 24 || &header[1..4] != b"PNG" {
+        return None;
+    }
+    let width = u32::from_be_bytes(header[16..20].try_into().unwrap());
+    let height = u32::from_be_bytes(header[20..24].try_into().unwrap());
+    Some((width, height))
+}
+
+/// Index just past the block that starts at `index`, i.e. the first later
+/// line that is not indented deeper than it.
+pub fn block_end(lines: &[ParseLogicalLine], index: usize) -> usize {
+    let indent = lines[index].indent;
+    lines[index + 1..]
+        .iter()
+        .position(|line| line.indent <= indent)
+        .map_or(lines.len(), |offset| index + 1 + offset)
+}
+
+/// Every defined character's key with the name it is shown with.
+pub fn character_names(lines: &[ParseLogicalLine]) -> HashMap<&str, &str> {
+    let mut names = HashMap::new();
+    for line in lines {
+        if let ParseStatement::Definition { key, character } = &line.statement {
+            names.insert(key.as_str(), character.name.as_str());
+        }
+    }
+    names
+}
+
+/// The name a say statement is shown with, or `None` for narration. A
+/// character with an empty name narrates, as in the game, and one that is
+/// never defined is shown by its key.
+pub fn speaker_name<'a>(speaker: &'a Speaker, names: &HashMap<&str, &'a str>) -> Option<&'a str> {
+    let name = match speaker {
+        Speaker::Narrator => return None,
+        Speaker::Character(key) => names.get(key.as_str()).copied().unwrap_or(key),
+        Speaker::Inline(name) => name,
+    };
+    Some(name).filter(|name| !name.is_empty())
+}
+
+/// Warns about a link that would point at no page, such as a jump to an
+/// undefined label or the last page's Next, which has no page after it.
+pub fn report_broken_link(line: usize, link: &str, target: &str) {
+    eprintln!(
+        "Warning: line {}: {} links to missing target `{}`, drawn disabled",
+        line, link, target
+    );
+}
+
+/// Escapes text for HTML and XHTML, both between tags and in quoted
+/// attributes.
+pub fn escape_for_html(text: &str) -> String {
+    text.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+        .replace("'", "&#39;")
+}
+
+/// Splits the script into its labels, each with the lines up to the next
+/// label. Anything before the first label is left out.
+pub fn label_bodies(lines: &[ParseLogicalLine]) -> Vec<(&str, &[ParseLogicalLine])> {
+    let starts: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| matches!(line.statement, ParseStatement::Label { .. }))
+        .map(|(index, _)| index)
+        .collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(number, start)| {
+            let end = starts.get(number + 1).copied().unwrap_or(lines.len());
+            let ParseStatement::Label { key } = &lines[*start].statement else {
+                unreachable!()
+            };
+            (key.as_str(), &lines[start + 1..end])
+        })
+        .collect()
+}
+
+/// A version 4 style UUID derived from `seed`, so exporting the same script
+/// again keeps its identity in Twine's or an e-reader's library.
+pub fn stable_uuid(seed: &str) -> String {
+    let hash = |offset: u64| {
+        seed.bytes().fold(offset, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    };
+    let high = hash(0xcbf29ce484222325);
+    let low = hash(0x84222325cbf29ce4);
+    let bytes: Vec<u8> = high
+        .to_be_bytes()
+        .into_iter()
+        .chain(low.to_be_bytes())
+        .collect();
+    let hex: String = bytes
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| match index {
+            6 => format!("{:02X}", (byte & 0x0F) | 0x40),
+            8 => format!("{:02X}", (byte & 0x3F) | 0x80),
+            _ => format!("{:02X}", byte),
+        })
+        .collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transform, Command};
+
+    fn options(twine_format: &str) -> Options {
+        Options {
+            command: Command::Render,
+            input: "script.rpy".to_string(),
+            output: "output".to_string(),
+            images: None,
+            screen_width: transform::SCREEN_WIDTH,
+            strict: false,
+            transforms: Vec::new(),
+            backend: "beamer".to_string(),
+            theme: "rentex".to_string(),
+            theme_options: Vec::new(),
+            collapse_dialogue: false,
+            twine_format: twine_format.to_string(),
+            font: None,
+            severities: Vec::new(),
+            metadata: Metadata::default(),
+            cover: false,
+        }
+    }
+
+    #[test]
+    fn finds_backends_by_name() {
+        let sugarcube = options("sugarcube");
+        for name in BACKEND_NAMES {
+            assert!(backend_by_name(name, &sugarcube).is_some(), "{}", name);
+        }
+        assert!(backend_by_name("docx", &sugarcube).is_none());
+        assert!(backend_by_name("twee", &options("snowman")).is_none());
+        // Only the page backends need the game traversed.
+        assert!(backend_by_name("beamer", &sugarcube).unwrap().uses_pages());
+        assert!(!backend_by_name("markdown", &sugarcube)
+            .unwrap()
+            .uses_pages());
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape_for_html("<b>\"Tom\" & 'Jerry'</b>"),
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;"
+        );
+        assert_eq!(escape_for_html("&lt;"), "&amp;lt;");
+    }
+}
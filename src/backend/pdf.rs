@@ -0,0 +1,892 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use miniz_oxide::deflate::{compress_to_vec_zlib, CompressionLevel};
+use pdf_writer::types::{ActionType, AnnotationType, CidFontType, FontFlags, SystemInfo};
+use pdf_writer::{Content, Filter, Finish, Name, Rect, Ref, Str, TextStr};
+use ttf_parser::{name_id, Face};
+
+use super::{Backend, Document, Images, OutputFile};
+use crate::{html_color, MenuChoice, Metadata, Page, PageText};
+
+/// A PDF written directly, without a LaTeX toolchain. Pages are laid out
+/// like the Beamer frames: full-bleed background, sprites along the bottom
+/// and a translucent textbox, with Next buttons and choices as links.
+pub struct Pdf {
+    /// TrueType font to embed, or one of the usual system fonts if unset.
+    pub font: Option<String>,
+}
+
+impl Backend for Pdf {
+    fn render(&self, document: &Document) -> Result<Vec<OutputFile>, String> {
+        let path = match &self.font {
+            Some(path) => path.clone(),
+            None => FONT_PATHS
+                .iter()
+                .find(|path| fs::exists(path).unwrap_or(false))
+                .ok_or("No system font found for the PDF backend, pass one with --font PATH")?
+                .to_string(),
+        };
+        let font_data = fs::read(&path).map_err(|error| format!("{}: {}", path, error))?;
+        Ok(vec![OutputFile {
+            path: "out.pdf".to_string(),
+            contents: pdf_output(document, &font_data, &path)?,
+        }])
+    }
+}
+
+/// Fonts tried in order when `--font` isn't given.
+const FONT_PATHS: [&str; 7] = [
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+/// A 16:9 page, half the size of a 1920x1080 screen in points.
+const PAGE_WIDTH: f32 = 960.0;
+const PAGE_HEIGHT: f32 = 540.0;
+const FONT_SIZE: f32 = 20.0;
+const LINE_HEIGHT: f32 = 26.0;
+const MARGIN: f32 = 15.0;
+const PADDING: f32 = 18.0;
+const TEXTBOX_HEIGHT: f32 = 0.3 * PAGE_HEIGHT;
+const TEXTBOX_OPACITY: f32 = 0.75;
+/// Height of the textbox left for dialogue and choices, from the top of the
+/// first line to the baseline of the last, above the Next button.
+const TEXT_HEIGHT: f32 = TEXTBOX_HEIGHT - 1.5 * PADDING - FONT_SIZE;
+const LINK_COLOR: [f32; 3] = [0.6, 0.8, 1.0];
+const DISABLED_COLOR: [f32; 3] = [0.6, 0.6, 0.6];
+
+const FONT_NAME: Name = Name(b"F1");
+const TRANSLUCENT: Name = Name(b"GS1");
+
+/// The embedded font, with the glyphs used so far for its width table and
+/// `ToUnicode` map.
+struct Font<'a> {
+    face: Face<'a>,
+    glyphs: BTreeMap<u16, char>,
+}
+
+impl Font<'_> {
+    fn width(&self, text: &str) -> f32 {
+        text.chars()
+            .map(|c| {
+                let glyph = self.face.glyph_index(c).unwrap_or_default();
+                self.face.glyph_hor_advance(glyph).unwrap_or(0) as f32
+            })
+            .sum::<f32>()
+            * FONT_SIZE
+            / self.face.units_per_em() as f32
+    }
+
+    /// Encodes `text` as two-byte glyph ids for the Identity-H encoding.
+    fn encode(&mut self, text: &str) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for c in text.chars() {
+            let glyph = self.face.glyph_index(c).unwrap_or_default().0;
+            self.glyphs.entry(glyph).or_insert(c);
+            encoded.extend(glyph.to_be_bytes());
+        }
+        encoded
+    }
+
+    /// Splits `text` into lines no wider than `width`, breaking at spaces.
+    fn wrap(&self, text: &str, width: f32) -> Vec<String> {
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let mut line = String::new();
+            for word in paragraph.split(' ') {
+                let candidate = if line.is_empty() {
+                    word.to_string()
+                } else {
+                    format!("{} {}", line, word)
+                };
+                if self.width(&candidate) > width && !line.is_empty() {
+                    lines.push(line);
+                    line = word.to_string();
+                } else {
+                    line = candidate;
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+/// A line of the textbox, with the page it links to if it is a choice.
+struct TextLine {
+    text: String,
+    x: f32,
+    choice: bool,
+    link: Option<usize>,
+}
+
+/// Spacing between lines of text at `size`.
+fn line_height(size: f32) -> f32 {
+    size * LINE_HEIGHT / FONT_SIZE
+}
+
+/// The dialogue and choices wrapped to the textbox at font size `size`.
+fn textbox_lines(
+    font: &Font,
+    text: &str,
+    choices: Option<&Vec<MenuChoice>>,
+    label_pages: &HashMap<&str, usize>,
+    size: f32,
+) -> Vec<TextLine> {
+    // Widths are measured at FONT_SIZE, so scale the room up instead.
+    let scale = FONT_SIZE / size;
+    let text_width = PAGE_WIDTH - 2.0 * (MARGIN + PADDING);
+    let mut lines: Vec<TextLine> = font
+        .wrap(text, text_width * scale)
+        .into_iter()
+        .map(|line| TextLine {
+            text: line,
+            x: MARGIN + PADDING,
+            choice: false,
+            link: None,
+        })
+        .collect();
+    for choice in choices.into_iter().flatten() {
+        let link = label_pages.get(choice.jump_key.as_str()).copied();
+        let line = match &choice.condition {
+            Some(condition) => format!("• {} (if {})", choice.text, condition),
+            None => format!("• {}", choice.text),
+        };
+        for line in font.wrap(&line, (text_width - PADDING) * scale) {
+            lines.push(TextLine {
+                text: line,
+                x: MARGIN + 2.0 * PADDING,
+                choice: true,
+                link,
+            });
+        }
+    }
+    lines
+}
+
+/// An image XObject written to the file, with its pixel size.
+#[derive(Clone, Copy)]
+struct Image {
+    id: Ref,
+    width: u32,
+    height: u32,
+}
+
+/// A link drawn on a page, pointing at another page.
+struct Link {
+    rect: Rect,
+    page: usize,
+}
+
+/// What every page refers to: the page tree, the shared resources and the
+/// pages links can point at.
+#[derive(Clone, Copy)]
+struct PageTree<'a> {
+    id: Ref,
+    font_id: Ref,
+    translucent_id: Ref,
+    page_ids: &'a [Ref],
+}
+
+fn pdf_output(document: &Document, font_data: &[u8], font_path: &str) -> Result<Vec<u8>, String> {
+    let pages: &[Page] = &document.pages;
+    let metadata: &Metadata = &document.metadata;
+    let face = Face::parse(font_data, 0).map_err(|error| format!("{}: {}", font_path, error))?;
+    if face.tables().glyf.is_none() {
+        return Err(format!(
+            "{}: only fonts with TrueType outlines can be embedded",
+            font_path
+        ));
+    }
+    let mut font = Font {
+        face,
+        glyphs: BTreeMap::new(),
+    };
+
+    let mut pdf = pdf_writer::Pdf::new();
+    let mut next_id = Ref::new(1);
+    let catalog_id = next_id.bump();
+    let page_tree_id = next_id.bump();
+    let info_id = next_id.bump();
+    let font_id = next_id.bump();
+    let cid_font_id = next_id.bump();
+    let descriptor_id = next_id.bump();
+    let font_file_id = next_id.bump();
+    let to_unicode_id = next_id.bump();
+    let translucent_id = next_id.bump();
+    let cover_id = metadata.cover.as_ref().map(|_| next_id.bump());
+    let page_ids: Vec<Ref> = pages.iter().map(|_| next_id.bump()).collect();
+    let tree = PageTree {
+        id: page_tree_id,
+        font_id,
+        translucent_id,
+        page_ids: &page_ids,
+    };
+
+    let mut label_pages = HashMap::new();
+    for (index, page) in pages.iter().enumerate() {
+        for label in page.targets() {
+            label_pages.entry(label.as_str()).or_insert(index);
+        }
+    }
+
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id)
+        .kids(cover_id.into_iter().chain(page_ids.iter().copied()))
+        .count((cover_id.iter().count() + pages.len()) as i32);
+    pdf.document_info(info_id)
+        .title(TextStr(metadata.title()))
+        .author(TextStr(metadata.author()));
+    pdf.ext_graphics(translucent_id)
+        .non_stroking_alpha(TEXTBOX_OPACITY);
+
+    if let (Some(cover_id), Some(cover)) = (cover_id, &metadata.cover) {
+        let cover_image = embed_image_file(&mut pdf, &mut next_id, cover)?;
+        let mut content = Content::new();
+        draw_image(
+            &mut content,
+            cover_image.id,
+            [PAGE_WIDTH, 0.0, 0.0, PAGE_HEIGHT, 0.0, 0.0],
+        );
+
+        let mut lines = vec![metadata.title().to_string()];
+        if let Some(version) = &metadata.version {
+            lines.push(format!("Version {}", version));
+        }
+        lines.push(metadata.author().to_string());
+        if let Some(date) = &metadata.date {
+            lines.push(date.clone());
+        }
+        // The lines and a Start link, centred in a box like the textbox.
+        let width = lines
+            .iter()
+            .map(|line| font.width(line))
+            .fold(font.width("Start"), f32::max)
+            + 4.0 * PADDING;
+        let height = (lines.len() + 1) as f32 * LINE_HEIGHT + 2.0 * PADDING;
+        let top = (PAGE_HEIGHT + height) / 2.0;
+        content.save_state();
+        content.set_parameters(TRANSLUCENT);
+        content.set_fill_rgb(0.0, 0.0, 0.0);
+        content.rect((PAGE_WIDTH - width) / 2.0, top - height, width, height);
+        content.fill_nonzero();
+        content.restore_state();
+
+        let mut y = top - PADDING - FONT_SIZE;
+        for line in &lines {
+            let x = (PAGE_WIDTH - font.width(line)) / 2.0;
+            show_text(
+                &mut content,
+                &mut font,
+                line,
+                x,
+                y,
+                FONT_SIZE,
+                [1.0, 1.0, 1.0],
+            );
+            y -= LINE_HEIGHT;
+        }
+        let x = (PAGE_WIDTH - font.width("Start")) / 2.0;
+        show_text(
+            &mut content,
+            &mut font,
+            "Start",
+            x,
+            y,
+            FONT_SIZE,
+            LINK_COLOR,
+        );
+        let links = if pages.is_empty() {
+            Vec::new()
+        } else {
+            vec![Link {
+                rect: Rect::new(x, y - 6.0, x + font.width("Start"), y + FONT_SIZE),
+                page: 0,
+            }]
+        };
+        write_page(
+            &mut pdf,
+            &mut next_id,
+            tree,
+            cover_id,
+            content,
+            &[cover_image.id],
+            links,
+        );
+    }
+
+    let mut images = HashMap::<String, Option<Image>>::new();
+    for (index, page) in pages.iter().enumerate() {
+        let mut content = Content::new();
+        let mut used_images = Vec::new();
+        let mut links = Vec::new();
+
+        let mut image = |name: &str, pdf: &mut pdf_writer::Pdf| -> Result<Option<Image>, String> {
+            if !images.contains_key(name) {
+                let image = embed_image(pdf, &mut next_id, &document.images, name)?;
+                images.insert(name.to_string(), image);
+            }
+            let image = images[name];
+            if let Some(image) = image {
+                if !used_images.contains(&image.id) {
+                    used_images.push(image.id);
+                }
+            }
+            Ok(image)
+        };
+
+        if let Some(background) = &page.stage.background {
+            if let Some(background) = image(background, &mut pdf)? {
+                draw_image(
+                    &mut content,
+                    background.id,
+                    [PAGE_WIDTH, 0.0, 0.0, PAGE_HEIGHT, 0.0, 0.0],
+                );
+            }
+        }
+
+        let mut sprites = page.stage.sprites.clone();
+        sprites.sort_by_key(|sprite| sprite.zorder);
+        for sprite in &sprites {
+            let Some(sprite_image) = image(&sprite.image, &mut pdf)? else {
+                continue;
+            };
+            // Same placement as the Beamer overlay: the sprite's xanchor goes
+            // at xpos.
+            let width =
+                sprite_image.width as f32 / document.images.screen_width * sprite.zoom * PAGE_WIDTH;
+            let height = width * sprite_image.height as f32 / sprite_image.width as f32;
+            let x = sprite.left(width / PAGE_WIDTH) * PAGE_WIDTH;
+            draw_image(
+                &mut content,
+                sprite_image.id,
+                [width, 0.0, 0.0, height, x, 0.0],
+            );
+        }
+
+        let textbox_top = MARGIN + TEXTBOX_HEIGHT;
+        content.save_state();
+        content.set_parameters(TRANSLUCENT);
+        content.set_fill_rgb(0.0, 0.0, 0.0);
+        content.rect(MARGIN, MARGIN, PAGE_WIDTH - 2.0 * MARGIN, TEXTBOX_HEIGHT);
+        content.fill_nonzero();
+        content.restore_state();
+
+        let (character, text, choices) = match &page.text {
+            PageText::Dialogue { character, text } => (character, text, None),
+            PageText::Menu {
+                character,
+                text,
+                choices,
+            } => (character, text, Some(choices)),
+        };
+        if let Some(character) = character
+            .as_ref()
+            .filter(|character| !character.name.is_empty())
+        {
+            let [r, g, b] = html_color(&character.color)
+                .and_then(|color| u32::from_str_radix(&color, 16).ok())
+                .map_or([0.5, 0.5, 0.5], |color| {
+                    [color >> 16, color >> 8 & 0xFF, color & 0xFF].map(|part| part as f32 / 255.0)
+                });
+            let width = font.width(&character.name) + PADDING;
+            content.set_fill_rgb(r, g, b);
+            content.rect(
+                MARGIN + PADDING,
+                textbox_top - 4.0,
+                width,
+                LINE_HEIGHT + 4.0,
+            );
+            content.fill_nonzero();
+            show_text(
+                &mut content,
+                &mut font,
+                &character.name,
+                MARGIN + 1.5 * PADDING,
+                textbox_top + 4.0,
+                FONT_SIZE,
+                [0.0, 0.0, 0.0],
+            );
+        }
+
+        // Text that doesn't fit is shrunk until it does, so it never runs
+        // out of the textbox or onto the Next button.
+        let mut size = FONT_SIZE;
+        let lines = loop {
+            let lines = textbox_lines(&font, text, choices, &label_pages, size);
+            let height = size + lines.len().saturating_sub(1) as f32 * line_height(size);
+            if height <= TEXT_HEIGHT || size < 1.0 {
+                break lines;
+            }
+            size *= 0.9;
+        };
+        let mut y = textbox_top - PADDING - size;
+        for line in lines {
+            let color = match (line.choice, line.link) {
+                (false, _) => [1.0, 1.0, 1.0],
+                (true, Some(_)) => LINK_COLOR,
+                (true, None) => DISABLED_COLOR,
+            };
+            show_text(&mut content, &mut font, &line.text, line.x, y, size, color);
+            if let Some(target) = line.link {
+                let width = font.width(&line.text) * size / FONT_SIZE;
+                links.push(Link {
+                    rect: Rect::new(line.x, y - 0.3 * size, line.x + width, y + size),
+                    page: target,
+                });
+            }
+            y -= line_height(size);
+        }
+
+        let next = match &page.unconditional_jump {
+            Some(jump) => Some(label_pages.get(jump.as_str()).copied()),
+            None if !(choices.is_some() || page.end) => {
+                Some(Some(index + 1).filter(|next| *next < pages.len()))
+            }
+            None => None,
+        };
+        if let Some(target) = next {
+            let x = PAGE_WIDTH - MARGIN - PADDING - font.width("Next");
+            let y = MARGIN + PADDING / 2.0;
+            show_text(
+                &mut content,
+                &mut font,
+                "Next",
+                x,
+                y,
+                FONT_SIZE,
+                if target.is_some() {
+                    LINK_COLOR
+                } else {
+                    DISABLED_COLOR
+                },
+            );
+            if let Some(target) = target {
+                links.push(Link {
+                    rect: Rect::new(x, y - 6.0, x + font.width("Next"), y + FONT_SIZE),
+                    page: target,
+                });
+            }
+        }
+
+        write_page(
+            &mut pdf,
+            &mut next_id,
+            tree,
+            page_ids[index],
+            content,
+            &used_images,
+            links,
+        );
+    }
+
+    write_font(
+        &mut pdf,
+        &font,
+        font_data,
+        [
+            font_id,
+            cid_font_id,
+            descriptor_id,
+            font_file_id,
+            to_unicode_id,
+        ],
+    );
+
+    Ok(pdf.finish())
+}
+
+/// Writes a page with its content stream, the images it draws and its
+/// links.
+fn write_page(
+    pdf: &mut pdf_writer::Pdf,
+    next_id: &mut Ref,
+    tree: PageTree,
+    id: Ref,
+    content: Content,
+    used_images: &[Ref],
+    links: Vec<Link>,
+) {
+    let content_id = next_id.bump();
+    pdf.stream(content_id, &content.finish());
+
+    let mut pdf_page = pdf.page(id);
+    pdf_page
+        .media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+        .parent(tree.id)
+        .contents(content_id);
+    let mut resources = pdf_page.resources();
+    resources.fonts().pair(FONT_NAME, tree.font_id);
+    resources
+        .ext_g_states()
+        .pair(TRANSLUCENT, tree.translucent_id);
+    let mut x_objects = resources.x_objects();
+    for id in used_images {
+        x_objects.pair(Name(image_name(*id).as_bytes()), *id);
+    }
+    x_objects.finish();
+    resources.finish();
+    let mut annotations = pdf_page.annotations();
+    for link in links {
+        let mut annotation = annotations.push();
+        annotation
+            .subtype(AnnotationType::Link)
+            .rect(link.rect)
+            .border(0.0, 0.0, 0.0, None);
+        annotation
+            .action()
+            .action_type(ActionType::GoTo)
+            .destination()
+            .page(tree.page_ids[link.page])
+            .fit();
+    }
+    annotations.finish();
+    pdf_page.finish();
+}
+
+fn image_name(id: Ref) -> String {
+    format!("Im{}", id.get())
+}
+
+fn draw_image(content: &mut Content, id: Ref, matrix: [f32; 6]) {
+    content.save_state();
+    content.transform(matrix);
+    content.x_object(Name(image_name(id).as_bytes()));
+    content.restore_state();
+}
+
+fn show_text(
+    content: &mut Content,
+    font: &mut Font,
+    text: &str,
+    x: f32,
+    y: f32,
+    size: f32,
+    color: [f32; 3],
+) {
+    let [r, g, b] = color;
+    content.set_fill_rgb(r, g, b);
+    content.begin_text();
+    content.set_font(FONT_NAME, size);
+    content.next_line(x, y);
+    content.show(Str(&font.encode(text)));
+    content.end_text();
+}
+
+/// Writes the font as a Type0 font with Identity-H encoding, so any glyph
+/// in it can be used, with widths and a `ToUnicode` map for the glyphs the
+/// pages used.
+fn write_font(pdf: &mut pdf_writer::Pdf, font: &Font, data: &[u8], ids: [Ref; 5]) {
+    let [font_id, cid_font_id, descriptor_id, font_file_id, to_unicode_id] = ids;
+    let face = &font.face;
+    let scale = 1000.0 / face.units_per_em() as f32;
+    let base_font = face
+        .names()
+        .into_iter()
+        .filter(|name| name.name_id == name_id::POST_SCRIPT_NAME)
+        .find_map(|name| name.to_string())
+        .unwrap_or("RentexFont".to_string());
+    let system_info = SystemInfo {
+        registry: Str(b"Adobe"),
+        ordering: Str(b"Identity"),
+        supplement: 0,
+    };
+
+    pdf.type0_font(font_id)
+        .base_font(Name(base_font.as_bytes()))
+        .encoding_predefined(Name(b"Identity-H"))
+        .descendant_font(cid_font_id)
+        .to_unicode(to_unicode_id);
+
+    let mut cid_font = pdf.cid_font(cid_font_id);
+    cid_font
+        .subtype(CidFontType::Type2)
+        .base_font(Name(base_font.as_bytes()))
+        .system_info(system_info)
+        .font_descriptor(descriptor_id)
+        .cid_to_gid_map_predefined(Name(b"Identity"));
+    let mut widths = cid_font.widths();
+    for glyph in font.glyphs.keys() {
+        let advance = face
+            .glyph_hor_advance(ttf_parser::GlyphId(*glyph))
+            .unwrap_or(0);
+        widths.consecutive(*glyph, [advance as f32 * scale]);
+    }
+    widths.finish();
+    cid_font.finish();
+
+    let bbox = face.global_bounding_box();
+    pdf.font_descriptor(descriptor_id)
+        .name(Name(base_font.as_bytes()))
+        .flags(FontFlags::SYMBOLIC)
+        .bbox(Rect::new(
+            bbox.x_min as f32 * scale,
+            bbox.y_min as f32 * scale,
+            bbox.x_max as f32 * scale,
+            bbox.y_max as f32 * scale,
+        ))
+        .italic_angle(face.italic_angle())
+        .ascent(face.ascender() as f32 * scale)
+        .descent(face.descender() as f32 * scale)
+        .cap_height(face.capital_height().unwrap_or(face.ascender()) as f32 * scale)
+        .stem_v(80.0)
+        .font_file2(font_file_id);
+
+    let compressed = compress_to_vec_zlib(data, CompressionLevel::DefaultLevel as u8);
+    pdf.stream(font_file_id, &compressed)
+        .filter(Filter::FlateDecode)
+        .pair(Name(b"Length1"), data.len() as i32);
+
+    let mut cmap = pdf_writer::types::UnicodeCmap::new(Name(b"Custom"), system_info);
+    for (glyph, c) in &font.glyphs {
+        cmap.pair(*glyph, *c);
+    }
+    pdf.cmap(to_unicode_id, &cmap.finish());
+}
+
+/// Embeds the PNG for image `name`, or a `.jpg` of the same name, as an
+/// image XObject. Returns `None` when there is no such image.
+fn embed_image(
+    pdf: &mut pdf_writer::Pdf,
+    next_id: &mut Ref,
+    images: &Images,
+    name: &str,
+) -> Result<Option<Image>, String> {
+    let png_path = images.path(name, "png");
+    let jpeg_path = images.path(name, "jpg");
+    if fs::exists(&png_path).unwrap_or(false) {
+        embed_image_file(pdf, next_id, &png_path).map(Some)
+    } else if fs::exists(&jpeg_path).unwrap_or(false) {
+        embed_image_file(pdf, next_id, &jpeg_path).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Embeds a PNG, or a JPEG as-is, as an image XObject.
+fn embed_image_file(
+    pdf: &mut pdf_writer::Pdf,
+    next_id: &mut Ref,
+    path: &str,
+) -> Result<Image, String> {
+    if path.to_lowercase().ends_with(".png") {
+        let error = |error: png::DecodingError| format!("{}: {}", path, error);
+        let file = fs::File::open(path).map_err(|error| format!("{}: {}", path, error))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(error)?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).map_err(error)?;
+        let pixels = &pixels[..info.buffer_size()];
+
+        let (gray, channels) = match info.color_type {
+            png::ColorType::Grayscale => (true, 1),
+            png::ColorType::GrayscaleAlpha => (true, 2),
+            png::ColorType::Rgb => (false, 3),
+            png::ColorType::Rgba => (false, 4),
+            png::ColorType::Indexed => return Err(format!("{}: unexpected indexed colour", path)),
+        };
+        let has_alpha = channels % 2 == 0;
+        let color_channels = if has_alpha { channels - 1 } else { channels };
+        let mut color = Vec::with_capacity(pixels.len());
+        let mut alpha = Vec::new();
+        for pixel in pixels.chunks(channels) {
+            color.extend(&pixel[..color_channels]);
+            if has_alpha {
+                alpha.push(pixel[color_channels]);
+            }
+        }
+
+        let level = CompressionLevel::DefaultLevel as u8;
+        let id = next_id.bump();
+        let mask_id = has_alpha.then(|| next_id.bump());
+        let color = compress_to_vec_zlib(&color, level);
+        let mut image = pdf.image_xobject(id, &color);
+        image
+            .width(info.width as i32)
+            .height(info.height as i32)
+            .bits_per_component(8);
+        image.filter(Filter::FlateDecode);
+        if gray {
+            image.color_space().device_gray();
+        } else {
+            image.color_space().device_rgb();
+        }
+        if let Some(mask_id) = mask_id {
+            image.s_mask(mask_id);
+        }
+        image.finish();
+        if let Some(mask_id) = mask_id {
+            let alpha = compress_to_vec_zlib(&alpha, level);
+            let mut mask = pdf.image_xobject(mask_id, &alpha);
+            mask.width(info.width as i32)
+                .height(info.height as i32)
+                .bits_per_component(8);
+            mask.filter(Filter::FlateDecode);
+            mask.color_space().device_gray();
+        }
+        Ok(Image {
+            id,
+            width: info.width,
+            height: info.height,
+        })
+    } else {
+        let data = fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+        let (width, height, components) =
+            jpeg_size(&data).ok_or(format!("{}: could not read the JPEG header", path))?;
+        let id = next_id.bump();
+        let mut image = pdf.image_xobject(id, &data);
+        image
+            .width(width as i32)
+            .height(height as i32)
+            .bits_per_component(8);
+        image.filter(Filter::DctDecode);
+        match components {
+            1 => image.color_space().device_gray(),
+            4 => image.color_space().device_cmyk(),
+            _ => image.color_space().device_rgb(),
+        }
+        image.finish();
+        Ok(Image { id, width, height })
+    }
+}
+
+/// Reads the size and number of colour components from a JPEG's start of
+/// frame marker. JPEG data goes into the PDF as-is.
+fn jpeg_size(data: &[u8]) -> Option<(u32, u32, u8)> {
+    let mut index = 2;
+    while index + 9 < data.len() {
+        if data[index] != 0xFF {
+            return None;
+        }
+        let marker = data[index + 1];
+        // Any number of 0xFF fill bytes can come before a marker.
+        if marker == 0xFF {
+            index += 1;
+            continue;
+        }
+        // SOI, RSTn and TEM stand alone, without a length.
+        if matches!(marker, 0x01 | 0xD0..=0xD8) {
+            index += 2;
+            continue;
+        }
+        let length = u16::from_be_bytes([data[index + 2], data[index + 3]]) as usize;
+        // SOF0 to SOF15, except DHT, JPG and DAC.
+        if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            let height = u16::from_be_bytes([data[index + 5], data[index + 6]]) as u32;
+            let width = u16::from_be_bytes([data[index + 7], data[index + 8]]) as u32;
+            return Some((width, height, data[index + 9]));
+        }
+        index += 2 + length;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transform, Stage};
+
+    /// The first of the usual system fonts, for tests that need one to lay
+    /// out text. Those tests are ignored by default; run them with
+    /// `cargo test --features pdf -- --ignored` where a font is installed.
+    fn system_font() -> (Vec<u8>, &'static str) {
+        let path = FONT_PATHS
+            .iter()
+            .find(|path| fs::exists(path).unwrap_or(false))
+            .expect("no system TrueType font installed");
+        (fs::read(path).unwrap(), path)
+    }
+
+    fn page(labels: &[&str], text: PageText, end: bool) -> Page {
+        Page {
+            line: 1,
+            labels: labels.iter().map(|label| label.to_string()).collect(),
+            anchors: Vec::new(),
+            text,
+            stage: Stage::default(),
+            unconditional_jump: None,
+            end,
+        }
+    }
+
+    #[test]
+    fn reads_jpeg_sizes() {
+        // SOI, an APP0 segment to skip, then a baseline SOF0 for a 640x480
+        // RGB image.
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00];
+        jpeg.extend([0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01, 0xE0, 0x02, 0x80, 0x03]);
+        assert_eq!(jpeg_size(&jpeg), Some((640, 480, 3)));
+        // Fill bytes and standalone markers before the frame are skipped.
+        let mut padded = vec![0xFF, 0xD8, 0xFF, 0xFF, 0xFF, 0xD0, 0xFF, 0x01];
+        padded.extend(&jpeg[2..]);
+        assert_eq!(jpeg_size(&padded), Some((640, 480, 3)));
+        assert_eq!(jpeg_size(b"\x89PNG\r\n\x1a\n0000000000"), None);
+    }
+
+    #[test]
+    #[ignore = "needs a system TrueType font such as DejaVu Sans"]
+    fn links_choices_and_next_buttons() {
+        let (font_data, font_path) = system_font();
+        let menu = PageText::Menu {
+            character: None,
+            text: "Where to?".to_string(),
+            choices: vec![MenuChoice {
+                text: "Home".to_string(),
+                jump_key: "home".to_string(),
+                condition: None,
+            }],
+        };
+        let home = PageText::Dialogue {
+            character: None,
+            text: "Home at last.".to_string(),
+        };
+        let document = Document {
+            source: "script.rpy".to_string(),
+            metadata: Metadata::default(),
+            images: Images::new("images", ".", transform::SCREEN_WIDTH),
+            lines: Vec::new(),
+            pages: vec![
+                page(&["start"], menu, false),
+                page(&["home"], home, false),
+                page(
+                    &[],
+                    PageText::Dialogue {
+                        character: None,
+                        text: "End".to_string(),
+                    },
+                    true,
+                ),
+            ],
+        };
+        let pdf = pdf_output(&document, &font_data, font_path).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.starts_with("%PDF-"));
+        assert!(pdf.contains("/FontFile2"));
+        // The choice on the menu and the Next button on the page it leads to.
+        assert_eq!(pdf.matches("/Subtype /Link").count(), 2);
+    }
+
+    #[test]
+    #[ignore = "needs a system TrueType font such as DejaVu Sans"]
+    fn wraps_text_to_the_textbox() {
+        let (font_data, _) = system_font();
+        let font = Font {
+            face: Face::parse(&font_data, 0).unwrap(),
+            glyphs: BTreeMap::new(),
+        };
+        let text = "word ".repeat(100);
+        let lines = textbox_lines(&font, text.trim_end(), None, &HashMap::new(), FONT_SIZE);
+        assert!(lines.len() > 1);
+        let text_width = PAGE_WIDTH - 2.0 * (MARGIN + PADDING);
+        assert!(lines
+            .iter()
+            .all(|line| font.width(&line.text) <= text_width));
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    block_end, character_names, label_bodies, speaker_name, stable_uuid, Backend, Document,
+    OutputFile,
+};
+use crate::{
+    is_identifier, split_arguments, take_string_literal, ParseLogicalLine, ParseStatement,
+};
+
+/// A Twee 3 source for prototyping in Twine: one passage per label, with
+/// menu choices as links and `default` variables set up in the story
+/// format's init passage.
+pub struct Twee {
+    pub format: StoryFormat,
+}
+
+pub enum StoryFormat {
+    SugarCube,
+    Harlowe,
+}
+
+/// Names accepted by `--twine-format`.
+pub const STORY_FORMAT_NAMES: [&str; 2] = ["sugarcube", "harlowe"];
+
+impl StoryFormat {
+    pub fn by_name(name: &str) -> Option<StoryFormat> {
+        match name {
+            "sugarcube" => Some(StoryFormat::SugarCube),
+            "harlowe" => Some(StoryFormat::Harlowe),
+            _ => None,
+        }
+    }
+}
+
+impl Backend for Twee {
+    fn uses_pages(&self) -> bool {
+        false
+    }
+
+    fn render(&self, document: &Document) -> Result<Vec<OutputFile>, String> {
+        Ok(vec![OutputFile {
+            path: "out.twee".to_string(),
+            contents: twee_output(document, &self.format).into_bytes(),
+        }])
+    }
+}
+
+struct Passage {
+    name: String,
+    text: String,
+}
+
+/// Builds the passages for one label. Choices that do more than jump get a
+/// passage of their own, and so do named menus and whatever follows a menu
+/// that some choice falls through to.
+struct PassageWriter<'a> {
+    format: &'a StoryFormat,
+    names: &'a HashMap<&'a str, &'a str>,
+    /// Labels and named menus, which have passages of their own.
+    targets: &'a HashSet<&'a str>,
+    label: &'a str,
+    menus: usize,
+    passages: Vec<Passage>,
+}
+
+fn twee_output(document: &Document, format: &StoryFormat) -> String {
+    let lines = &document.lines;
+    let names = character_names(lines);
+
+    let (format_name, format_version) = match format {
+        StoryFormat::SugarCube => ("SugarCube", "2.37.3"),
+        StoryFormat::Harlowe => ("Harlowe", "3.3.9"),
+    };
+    let mut output = format!(":: StoryTitle\n{}\n\n", document.metadata.title());
+    output += format!(
+        ":: StoryData\n{{\n  \"ifid\": \"{}\",\n  \"format\": \"{}\",\n  \"format-version\": \"{}\",\n  \"start\": \"start\"\n}}\n\n",
+        stable_uuid(&document.source),
+        format_name,
+        format_version
+    )
+    .as_str();
+
+    let mut init = String::new();
+    for line in lines {
+        if let ParseStatement::Default { name, value } = &line.statement {
+            init += match story_value(value, format).filter(|_| is_identifier(name)) {
+                Some(value) => match format {
+                    StoryFormat::SugarCube => format!("<<set ${} to {}>>\n", name, value),
+                    StoryFormat::Harlowe => format!("(set: ${} to {})\n", name, value),
+                },
+                None => match format {
+                    StoryFormat::SugarCube => format!("/* default {} = {} */\n", name, value),
+                    StoryFormat::Harlowe => format!("<!-- default {} = {} -->\n", name, value),
+                },
+            }
+            .as_str();
+        }
+    }
+    if !init.is_empty() {
+        output += match format {
+            StoryFormat::SugarCube => ":: StoryInit\n",
+            StoryFormat::Harlowe => ":: Startup [startup]\n",
+        };
+        output += format!("{}\n", init).as_str();
+    }
+
+    let labels = label_bodies(lines);
+    let targets: HashSet<&str> = lines
+        .iter()
+        .filter_map(|line| match &line.statement {
+            ParseStatement::Label { key } => Some(key.as_str()),
+            ParseStatement::Menu { name: Some(name) } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    for (number, (label, body)) in labels.iter().enumerate() {
+        let mut writer = PassageWriter {
+            format,
+            names: &names,
+            targets: &targets,
+            label,
+            menus: 0,
+            passages: Vec::new(),
+        };
+        let passage = writer.add_passage(label.to_string());
+        if let Some(passage) = writer.render_block(body, passage) {
+            if let Some((next, _)) = labels.get(number + 1) {
+                writer.passages[passage].text += format!("[[Continue->{}]]\n", next).as_str();
+            }
+        }
+        for passage in writer.passages {
+            output += format!(":: {}\n", escape_passage_name(&passage.name)).as_str();
+            if !passage.text.trim().is_empty() {
+                output += format!("{}\n", passage.text.trim_end()).as_str();
+            }
+            output += "\n";
+        }
+    }
+
+    output
+}
+
+impl PassageWriter<'_> {
+    fn add_passage(&mut self, name: String) -> usize {
+        self.passages.push(Passage {
+            name,
+            text: String::new(),
+        });
+        self.passages.len() - 1
+    }
+
+    /// Writes `lines` into the passage `current`, returning the passage play
+    /// ends up in if it runs off the end.
+    fn render_block(&mut self, lines: &[ParseLogicalLine], mut current: usize) -> Option<usize> {
+        let mut index = 0;
+        while index < lines.len() {
+            match &lines[index].statement {
+                ParseStatement::Dialogue { speaker, text } => {
+                    let text = escape_for_story(text, self.format);
+                    let line = match speaker_name(speaker, self.names) {
+                        Some(name) => {
+                            format!("{}: {}\n\n", escape_for_story(name, self.format), text)
+                        }
+                        None => format!("{}\n\n", text),
+                    };
+                    self.passages[current].text += line.as_str();
+                }
+                ParseStatement::Jump { key } if self.targets.contains(key.as_str()) => {
+                    self.passages[current].text += format!("[[Continue->{}]]\n", key).as_str();
+                    return None;
+                }
+                ParseStatement::Call { key } if self.targets.contains(key.as_str()) => {
+                    // Neither format can return from a passage, so a call
+                    // shows the called label in place.
+                    self.passages[current].text += match self.format {
+                        StoryFormat::SugarCube => format!("<<include \"{}\">>\n\n", key),
+                        StoryFormat::Harlowe => format!("(display: \"{}\")\n\n", key),
+                    }
+                    .as_str();
+                }
+                ParseStatement::Jump { key } | ParseStatement::Call { key } => {
+                    // A link to a passage that doesn't exist is broken, and so
+                    // is including one, so the story ends here instead.
+                    let statement = match lines[index].statement {
+                        ParseStatement::Call { .. } => "call",
+                        _ => "jump",
+                    };
+                    self.passages[current].text += match self.format {
+                        StoryFormat::SugarCube => format!(
+                            "/* {} to undefined label `{}` */\nTHE END\n",
+                            statement, key
+                        ),
+                        StoryFormat::Harlowe => format!(
+                            "<!-- {} to undefined label `{}` -->\nTHE END\n",
+                            statement, key
+                        ),
+                    }
+                    .as_str();
+                    return None;
+                }
+                ParseStatement::End {} => {
+                    self.passages[current].text += "THE END\n";
+                    return None;
+                }
+                ParseStatement::Menu { name } => {
+                    let end = block_end(lines, index);
+                    // Jumps to a named menu go to its own passage.
+                    if let Some(name) = name {
+                        self.passages[current].text += format!("[[Continue->{}]]\n", name).as_str();
+                        current = self.add_passage(name.clone());
+                    }
+                    current = self.render_menu(&lines[index + 1..end], current)?;
+                    index = end;
+                    continue;
+                }
+                _ => (),
+            }
+            index += 1;
+        }
+        Some(current)
+    }
+
+    /// Writes a menu as its caption and one link per choice. Returns the
+    /// passage for what follows the menu, if any choice gets there.
+    fn render_menu(&mut self, body: &[ParseLogicalLine], current: usize) -> Option<usize> {
+        self.menus += 1;
+        let menu = self.menus;
+        let after_name = format!("{} after menu {}", self.label, menu);
+        let mut falls_through = false;
+
+        let first_choice = body
+            .iter()
+            .position(|line| matches!(line.statement, ParseStatement::Choice { .. }))
+            .unwrap_or(body.len());
+        self.render_block(&body[..first_choice], current);
+
+        let mut links = String::new();
+        let mut index = first_choice;
+        let mut number = 0;
+        while index < body.len() {
+            let end = block_end(body, index);
+            if let ParseStatement::Choice { text, condition } = &body[index].statement {
+                number += 1;
+                let block = &body[index + 1..end];
+                let target = match block.first().map(|line| &line.statement) {
+                    Some(ParseStatement::Jump { key }) if self.targets.contains(key.as_str()) => {
+                        key.clone()
+                    }
+                    None => {
+                        falls_through = true;
+                        after_name.clone()
+                    }
+                    Some(_) => {
+                        let name = format!("{} menu {} choice {}", self.label, menu, number);
+                        let passage = self.add_passage(name.clone());
+                        if let Some(passage) = self.render_block(block, passage) {
+                            self.passages[passage].text +=
+                                format!("[[Continue->{}]]\n", after_name).as_str();
+                            falls_through = true;
+                        }
+                        name
+                    }
+                };
+                if let Some(condition) = condition {
+                    links += match self.format {
+                        StoryFormat::SugarCube => format!("/* if {} */ ", condition),
+                        StoryFormat::Harlowe => format!("<!-- if {} --> ", condition),
+                    }
+                    .as_str();
+                }
+                links += format!("{}\n", story_link(text, &target, self.format)).as_str();
+            }
+            index = end;
+        }
+        self.passages[current].text += links.as_str();
+
+        if falls_through {
+            Some(self.add_passage(after_name))
+        } else {
+            None
+        }
+    }
+}
+
+/// Converts a Ren'Py `default` value to the story format's syntax, for the
+/// literals both languages share.
+fn story_value(value: &str, format: &StoryFormat) -> Option<String> {
+    let value = value.trim();
+    match value {
+        "True" => return Some("true".to_string()),
+        "False" => return Some("false".to_string()),
+        "None" => {
+            return match format {
+                StoryFormat::SugarCube => Some("null".to_string()),
+                StoryFormat::Harlowe => None,
+            }
+        }
+        _ => (),
+    }
+    if value.parse::<f64>().is_ok_and(|number| number.is_finite()) {
+        return Some(value.to_string());
+    }
+    if take_string_literal(value).is_some_and(|(_, rest)| rest.is_empty()) {
+        return Some(value.to_string());
+    }
+    if let Some(items) = value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+    {
+        let items = split_arguments(items)
+            .iter()
+            .map(|item| story_value(item, format))
+            .collect::<Option<Vec<String>>>()?;
+        return Some(match format {
+            StoryFormat::SugarCube => format!("[{}]", items.join(", ")),
+            StoryFormat::Harlowe => format!("(a: {})", items.join(", ")),
+        });
+    }
+    if value
+        .strip_prefix('{')
+        .and_then(|value| value.strip_suffix('}'))
+        .is_some_and(|items| items.trim().is_empty())
+    {
+        return Some(match format {
+            StoryFormat::SugarCube => "{}".to_string(),
+            StoryFormat::Harlowe => "(dm:)".to_string(),
+        });
+    }
+    None
+}
+
+/// Character sequences either story format reads as markup, variables or
+/// macros.
+const STORY_MARKUP: [&str; 16] = [
+    "//", "''", "__", "~~", "^^", "==", "@@", "{{", "[[", "]]", "<<", "/*", "<!--", "$", "\\", "`",
+];
+
+/// Characters Harlowe gives a meaning that SugarCube doesn't: emphasis,
+/// macro calls, hooks and temporary variables.
+const HARLOWE_MARKUP: [char; 7] = ['*', '(', '[', ']', '|', '<', '_'];
+
+fn needs_escaping(text: &str, format: &StoryFormat) -> bool {
+    STORY_MARKUP.iter().any(|markup| text.contains(markup))
+        || matches!(format, StoryFormat::Harlowe) && text.contains(HARLOWE_MARKUP)
+}
+
+/// Shows `text` as written, in the story format's verbatim markup when it
+/// has anything that would be read as markup.
+fn escape_for_story(text: &str, format: &StoryFormat) -> String {
+    if !needs_escaping(text, format) {
+        return text.to_string();
+    }
+    match format {
+        StoryFormat::SugarCube if !text.contains("\"\"\"") => format!("\"\"\"{}\"\"\"", text),
+        StoryFormat::SugarCube => format!("<nowiki>{}</nowiki>", text.replace("<", "&lt;")),
+        StoryFormat::Harlowe => {
+            // Verbatim markup is closed by the same number of backticks it
+            // was opened with, so use one more than the text has in a row.
+            let longest = text
+                .split(|c| c != '`')
+                .map(|run| run.len())
+                .max()
+                .unwrap_or(0);
+            let ticks = "`".repeat(longest + 1);
+            format!("{}{}{}", ticks, text, ticks)
+        }
+    }
+}
+
+/// A link to the passage `target`. Text that `->`, `|` or brackets would
+/// cut short goes in a link macro instead of a `[[link]]`.
+fn story_link(text: &str, target: &str, format: &StoryFormat) -> String {
+    let breaks_link = ["->", "<-", "|", "[", "]"]
+        .iter()
+        .any(|markup| text.contains(markup));
+    if !breaks_link && !needs_escaping(text, format) {
+        return format!("[[{}->{}]]", text, target);
+    }
+    let quote = |text: &str| format!("\"{}\"", text.replace("\\", "\\\\").replace("\"", "\\\""));
+    let text = escape_for_story(text, format);
+    match format {
+        StoryFormat::SugarCube => {
+            format!("<<link {} {}>><</link>>", quote(&text), quote(target))
+        }
+        StoryFormat::Harlowe => format!("(link-goto: {}, {})", quote(&text), quote(target)),
+    }
+}
+
+fn escape_passage_name(name: &str) -> String {
+    let mut escaped = String::new();
+    for c in name.chars() {
+        if matches!(c, '\\' | '[' | ']' | '{' | '}') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn twee(script: &str) -> String {
+        let document = Document {
+            source: "script.rpy".to_string(),
+            metadata: crate::Metadata::default(),
+            images: crate::backend::Images::new("images", ".", crate::transform::SCREEN_WIDTH),
+            lines: crate::parse_test_script(script),
+            pages: Vec::new(),
+        };
+        twee_output(&document, &StoryFormat::SugarCube)
+    }
+
+    #[test]
+    fn ends_at_undefined_labels() {
+        let output = twee(
+            "label start:\n    menu:\n        \"Stay\":\n            jump start\n        \"Leave\":\n            jump nowhere\n",
+        );
+        assert!(output.contains("[[Stay->start]]\n[[Leave->start menu 1 choice 2]]\n"));
+        assert!(output.contains(
+            ":: start menu 1 choice 2\n/* jump to undefined label `nowhere` */\nTHE END\n"
+        ));
+        assert!(!output.contains("->nowhere"));
+
+        let output = twee("label start:\n    call nowhere\n    \"After\"\n");
+        assert!(output.contains(":: start\n/* call to undefined label `nowhere` */\nTHE END\n"));
+        assert!(!output.contains("<<include"));
+        assert!(!output.contains("After"));
+    }
+
+    #[test]
+    fn escapes_story_markup() {
+        let sugarcube = StoryFormat::SugarCube;
+        let harlowe = StoryFormat::Harlowe;
+        assert_eq!(escape_for_story("Hello.", &sugarcube), "Hello.");
+        assert_eq!(escape_for_story("Hello.", &harlowe), "Hello.");
+        assert_eq!(
+            escape_for_story("It costs $5.", &sugarcube),
+            "\"\"\"It costs $5.\"\"\""
+        );
+        assert_eq!(
+            escape_for_story("Say \"\"\"hi\"\"\" <<now>>", &sugarcube),
+            "<nowiki>Say \"\"\"hi\"\"\" &lt;&lt;now>></nowiki>"
+        );
+        assert_eq!(escape_for_story("*wink*", &sugarcube), "*wink*");
+        assert_eq!(escape_for_story("*wink*", &harlowe), "`*wink*`");
+        assert_eq!(escape_for_story("a `b` $c", &harlowe), "``a `b` $c``");
+    }
+
+    #[test]
+    fn links_with_markup_use_macros() {
+        let sugarcube = StoryFormat::SugarCube;
+        let harlowe = StoryFormat::Harlowe;
+        assert_eq!(story_link("Go", "next", &sugarcube), "[[Go->next]]");
+        assert_eq!(
+            story_link("Left|Right", "next", &sugarcube),
+            "<<link \"Left|Right\" \"next\">><</link>>"
+        );
+        assert_eq!(
+            story_link("Left|Right", "next", &harlowe),
+            "(link-goto: \"`Left|Right`\", \"next\")"
+        );
+    }
+}
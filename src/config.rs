@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::lint::{self, Severity};
+use crate::metadata::Metadata;
+
+/// Name of the project file, looked for in the current directory and then
+/// in each directory above it.
+pub const CONFIG_FILE: &str = "rentex.toml";
+
+/// Settings from `rentex.toml`, so a team can commit its conversion
+/// settings next to the game. Command line options override them, and
+/// paths are relative to the directory the file is in.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The script to convert.
+    pub input: Option<String>,
+    /// Directory the output files are written to.
+    pub output: Option<String>,
+    /// Directory the background and sprite PNGs are read from, if not
+    /// `images` in the output directory.
+    pub images: Option<String>,
+    pub backend: Option<String>,
+    pub strict: Option<bool>,
+    /// Width in pixels of the game's screen, for `xpos` and sprite sizes.
+    pub screen_width: Option<f32>,
+    pub theme: Option<String>,
+    pub theme_options: Vec<String>,
+    pub collapse_dialogue: Option<bool>,
+    pub twine_format: Option<String>,
+    pub font: Option<String>,
+    /// Transform names to the xalign they put sprites at, as with
+    /// `--transform`.
+    pub transforms: BTreeMap<String, f32>,
+    /// Lint rules to their level, as with `--severity`.
+    pub severity: BTreeMap<String, Severity>,
+    pub metadata: MetadataConfig,
+}
+
+/// The `[metadata]` table, which overrides what the script defines.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetadataConfig {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub date: Option<String>,
+    /// Whether to add a cover page.
+    pub cover: Option<bool>,
+    /// Image for the cover page instead of the main menu background.
+    pub cover_image: Option<String>,
+}
+
+impl MetadataConfig {
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            title: self.title.clone(),
+            author: self.author.clone(),
+            version: self.version.clone(),
+            date: self.date.clone(),
+            cover: self.cover_image.clone(),
+        }
+    }
+}
+
+impl Config {
+    /// Checks the values serde can't, such as rule names.
+    fn validate(&self) -> Result<(), String> {
+        if let Some(format) = &self.twine_format {
+            if !crate::backend::STORY_FORMAT_NAMES.contains(&format.as_str()) {
+                return Err(format!(
+                    "unknown twine_format `{}`, expected one of: {}",
+                    format,
+                    crate::backend::STORY_FORMAT_NAMES.join(", ")
+                ));
+            }
+        }
+        if let Some(backend) = &self.backend {
+            if !crate::backend::BACKEND_NAMES.contains(&backend.as_str()) {
+                return Err(format!(
+                    "unknown backend `{}`, expected one of: {}",
+                    backend,
+                    crate::backend::BACKEND_NAMES.join(", ")
+                ));
+            }
+        }
+        if let Some(width) = self.screen_width {
+            if width <= 0.0 {
+                return Err(format!("screen_width must be positive, not {}", width));
+            }
+        }
+        for rule in self.severity.keys() {
+            if !lint::RULES.iter().any(|(name, _)| name == rule) {
+                let names: Vec<&str> = lint::RULES.iter().map(|(name, _)| *name).collect();
+                return Err(format!(
+                    "unknown lint rule `{}` in [severity], expected one of: {}",
+                    rule,
+                    names.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the paths in the file relative to the current directory.
+    fn resolve_paths(&mut self, root: &Path) {
+        let paths = [
+            &mut self.input,
+            &mut self.output,
+            &mut self.images,
+            &mut self.font,
+            &mut self.metadata.cover_image,
+        ];
+        for path in paths.into_iter().flatten() {
+            *path = root.join(&*path).to_string_lossy().to_string();
+        }
+    }
+}
+
+/// Finds `rentex.toml` in the current directory or the nearest one above
+/// it. The path is relative, so paths built from it stay short and the same
+/// on every machine.
+fn find_config() -> Option<PathBuf> {
+    let current = std::env::current_dir().ok()?;
+    let mut relative = PathBuf::new();
+    for directory in current.ancestors() {
+        if directory.join(CONFIG_FILE).is_file() {
+            return Some(relative.join(CONFIG_FILE));
+        }
+        relative.push("..");
+    }
+    None
+}
+
+/// Reads and checks the project's `rentex.toml`, or returns the defaults if
+/// there isn't one.
+pub fn load_config() -> Result<Config, String> {
+    let Some(path) = find_config() else {
+        return Ok(Config::default());
+    };
+    let error = |error: String| format!("{}: {}", path.display(), error);
+    let contents = std::fs::read_to_string(&path).map_err(|e| error(e.to_string()))?;
+    let mut config: Config = toml::from_str(&contents).map_err(|e| error(e.to_string()))?;
+    config.validate().map_err(error)?;
+    config.resolve_paths(path.parent().unwrap());
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(contents: &str) -> Result<(), String> {
+        toml::from_str::<Config>(contents).unwrap().validate()
+    }
+
+    #[test]
+    fn accepts_known_values() {
+        assert_eq!(
+            validate(
+                "backend = \"html\"\ntwine_format = \"harlowe\"\nscreen_width = 1280\n\n[severity]\nempty-menu = \"off\"\n"
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_values() {
+        assert!(validate("backend = \"word\"\n")
+            .unwrap_err()
+            .starts_with("unknown backend `word`"));
+        assert!(validate("twine_format = \"snowman\"\n")
+            .unwrap_err()
+            .starts_with("unknown twine_format `snowman`"));
+        assert!(validate("screen_width = 0\n").is_err());
+        assert!(validate("[severity]\nmissing-alt = \"error\"\n")
+            .unwrap_err()
+            .starts_with("unknown lint rule `missing-alt`"));
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::backend::block_end;
+use crate::{ParseLogicalLine, ParseStatement};
+
+/// The story's control flow: labels, menus and endings joined by the jumps,
+/// choices, calls and fall-throughs between them. Say statements live on the
+/// edges, since they are what the player reads on the way from one node to
+/// the next.
+#[derive(Debug, Clone)]
+pub struct FlowGraph {
+    pub nodes: Vec<FlowNode>,
+    pub edges: Vec<FlowEdge>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FlowNode {
+    pub kind: NodeKind,
+    /// Source line of the statement the node was made from.
+    pub line: usize,
+    /// Whether the node can be reached from `label start`.
+    pub reachable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    Label {
+        key: String,
+    },
+    Menu {
+        name: Option<String>,
+    },
+    /// Where play continues after a menu, when at least one choice does not
+    /// jump away.
+    AfterMenu,
+    /// A `return`.
+    End,
+}
+
+#[derive(Debug, Clone)]
+pub struct FlowEdge {
+    pub from: usize,
+    pub to: Target,
+    pub kind: EdgeKind,
+    /// Source line of the statement that leads along the edge.
+    pub line: usize,
+    /// Indices into the logical lines of the say statements on the way.
+    pub dialogue: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Node(usize),
+    /// A jump or call to a label that is never defined.
+    Missing(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EdgeKind {
+    /// Running off the end of a label into the next one, or into a menu.
+    FallThrough,
+    Jump,
+    Choice {
+        text: String,
+        condition: Option<String>,
+    },
+    Call,
+    Return,
+}
+
+/// Where play currently is while the graph is built: the node it left and
+/// what it has passed through since.
+struct Cursor {
+    from: usize,
+    kind: EdgeKind,
+    line: usize,
+    dialogue: Vec<usize>,
+}
+
+impl FlowGraph {
+    pub fn build(lines: &[ParseLogicalLine]) -> FlowGraph {
+        let mut graph = FlowGraph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        };
+
+        let label_lines: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| matches!(line.statement, ParseStatement::Label { .. }))
+            .map(|(index, _)| index)
+            .collect();
+        let mut labels = HashMap::<String, usize>::new();
+        for index in &label_lines {
+            if let ParseStatement::Label { key } = &lines[*index].statement {
+                let node = graph.add_node(NodeKind::Label { key: key.clone() }, lines[*index].line);
+                labels.entry(key.clone()).or_insert(node);
+            }
+        }
+
+        for (label_number, start) in label_lines.iter().enumerate() {
+            let end = label_lines
+                .get(label_number + 1)
+                .copied()
+                .unwrap_or(lines.len());
+            let cursor = Cursor {
+                from: label_number,
+                kind: EdgeKind::FallThrough,
+                line: lines[*start].line,
+                dialogue: Vec::new(),
+            };
+            if let Some(cursor) = graph.walk(lines, start + 1, end, Some(cursor)) {
+                if label_number + 1 < label_lines.len() {
+                    graph.add_edge(cursor, Target::Node(label_number + 1));
+                }
+            }
+        }
+
+        // Named menus are jump targets too, unless a label has the name.
+        for (node, menu) in graph.nodes.iter().enumerate() {
+            if let NodeKind::Menu { name: Some(name) } = &menu.kind {
+                labels.entry(name.clone()).or_insert(node);
+            }
+        }
+        for edge in graph.edges.iter_mut() {
+            if let Target::Missing(key) = &edge.to {
+                if let Some(node) = labels.get(key) {
+                    edge.to = Target::Node(*node);
+                }
+            }
+        }
+
+        if let Some(start) = labels.get("start") {
+            graph.mark_reachable(*start);
+        }
+
+        graph
+    }
+
+    /// Nodes play can get stuck at: not an ending, yet with nowhere to go.
+    pub fn is_dead_end(&self, node: usize) -> bool {
+        self.nodes[node].kind != NodeKind::End
+            && !self
+                .edges
+                .iter()
+                .any(|edge| edge.from == node && edge.kind != EdgeKind::Call)
+    }
+
+    /// The node for `label start`, where play begins.
+    pub fn start(&self) -> Option<usize> {
+        self.nodes
+            .iter()
+            .position(|node| matches!(&node.kind, NodeKind::Label { key } if key == "start"))
+    }
+
+    /// The label whose body a node is in.
+    pub fn label_of(&self, node: usize) -> Option<&str> {
+        // Label nodes come first, in the order they are written.
+        self.nodes
+            .iter()
+            .take_while(|other| matches!(other.kind, NodeKind::Label { .. }))
+            .filter(|other| other.line <= self.nodes[node].line)
+            .filter_map(|other| match &other.kind {
+                NodeKind::Label { key } => Some(key.as_str()),
+                _ => None,
+            })
+            .last()
+    }
+
+    /// Every way from `label start` to a `return`, as the edges taken. Calls
+    /// are stepped over, and a path takes each edge at most once, so it
+    /// follows a loop at most once around. The search gives up after `limit`
+    /// paths; the flag says whether it did.
+    pub fn ending_paths(&self, limit: usize) -> (Vec<Vec<usize>>, bool) {
+        // Edges that can still lead to a `return`, by the node they leave,
+        // so the search never wanders where no path can end.
+        let steps: Vec<(usize, usize)> = self
+            .edges
+            .iter()
+            .filter(|edge| edge.kind != EdgeKind::Call)
+            .filter_map(|edge| match edge.to {
+                Target::Node(to) => Some((edge.from, to)),
+                Target::Missing(_) => None,
+            })
+            .collect();
+        let mut can_end: Vec<bool> = self
+            .nodes
+            .iter()
+            .map(|node| node.kind == NodeKind::End)
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (from, to) in &steps {
+                if can_end[*to] && !can_end[*from] {
+                    can_end[*from] = true;
+                    changed = true;
+                }
+            }
+        }
+        let mut outgoing = vec![Vec::new(); self.nodes.len()];
+        for (index, edge) in self.edges.iter().enumerate() {
+            if let Target::Node(to) = edge.to {
+                if edge.kind != EdgeKind::Call && can_end[to] {
+                    outgoing[edge.from].push((index, to));
+                }
+            }
+        }
+
+        let mut search = PathSearch {
+            graph: self,
+            outgoing,
+            path: Vec::new(),
+            taken: vec![false; self.edges.len()],
+            paths: Vec::new(),
+            steps: 0,
+            limit,
+        };
+        if let Some(start) = self.start() {
+            search.visit(start);
+        }
+        let truncated = search.paths.len() >= limit || search.steps >= limit * STEPS_PER_PATH;
+        (search.paths, truncated)
+    }
+
+    fn add_node(&mut self, kind: NodeKind, line: usize) -> usize {
+        self.nodes.push(FlowNode {
+            kind,
+            line,
+            reachable: false,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn add_edge(&mut self, cursor: Cursor, to: Target) {
+        self.edges.push(FlowEdge {
+            from: cursor.from,
+            to,
+            kind: cursor.kind,
+            line: cursor.line,
+            dialogue: cursor.dialogue,
+        });
+    }
+
+    /// Follows the statements in `lines[start..end]`, adding the nodes and
+    /// edges they make. Returns the cursor if play can run off the end.
+    /// Jump targets are recorded as missing and resolved once every label
+    /// is known.
+    fn walk(
+        &mut self,
+        lines: &[ParseLogicalLine],
+        start: usize,
+        end: usize,
+        mut cursor: Option<Cursor>,
+    ) -> Option<Cursor> {
+        let mut index = start;
+        while index < end {
+            let line = &lines[index];
+            match &line.statement {
+                ParseStatement::Dialogue { .. } => {
+                    if let Some(cursor) = cursor.as_mut() {
+                        cursor.dialogue.push(index);
+                    }
+                }
+                ParseStatement::Jump { key } => {
+                    if let Some(mut cursor) = cursor.take() {
+                        if cursor.kind == EdgeKind::FallThrough {
+                            cursor.kind = EdgeKind::Jump;
+                            cursor.line = line.line;
+                        }
+                        self.add_edge(cursor, Target::Missing(key.clone()));
+                    }
+                }
+                ParseStatement::Call { key } => {
+                    if let Some(cursor) = cursor.as_mut() {
+                        self.edges.push(FlowEdge {
+                            from: cursor.from,
+                            to: Target::Missing(key.clone()),
+                            kind: EdgeKind::Call,
+                            line: line.line,
+                            dialogue: Vec::new(),
+                        });
+                    }
+                }
+                ParseStatement::End {} => {
+                    if let Some(mut cursor) = cursor.take() {
+                        let node = self.add_node(NodeKind::End, line.line);
+                        if cursor.kind == EdgeKind::FallThrough {
+                            cursor.kind = EdgeKind::Return;
+                            cursor.line = line.line;
+                        }
+                        self.add_edge(cursor, Target::Node(node));
+                    }
+                }
+                ParseStatement::Menu { name } => {
+                    let menu_end = block_end(&lines[..end], index);
+                    let menu = self.add_node(NodeKind::Menu { name: name.clone() }, line.line);
+                    let first_choice = (index + 1..menu_end)
+                        .find(|choice| {
+                            matches!(lines[*choice].statement, ParseStatement::Choice { .. })
+                        })
+                        .unwrap_or(menu_end);
+                    if let Some(mut cursor) = cursor.take() {
+                        // A caption is said while the choices are on screen.
+                        cursor
+                            .dialogue
+                            .extend((index + 1..first_choice).filter(|caption| {
+                                matches!(lines[*caption].statement, ParseStatement::Dialogue { .. })
+                            }));
+                        self.add_edge(cursor, Target::Node(menu));
+                    }
+
+                    let mut fall_throughs = Vec::new();
+                    let mut choice = first_choice;
+                    while choice < menu_end {
+                        let choice_end = block_end(&lines[..menu_end], choice);
+                        if let ParseStatement::Choice { text, condition } = &lines[choice].statement
+                        {
+                            let choice_cursor = Cursor {
+                                from: menu,
+                                kind: EdgeKind::Choice {
+                                    text: text.clone(),
+                                    condition: condition.clone(),
+                                },
+                                line: lines[choice].line,
+                                dialogue: Vec::new(),
+                            };
+                            if let Some(fall_through) =
+                                self.walk(lines, choice + 1, choice_end, Some(choice_cursor))
+                            {
+                                fall_throughs.push(fall_through);
+                            }
+                        }
+                        choice = choice_end;
+                    }
+
+                    if !fall_throughs.is_empty() {
+                        let after = self.add_node(NodeKind::AfterMenu, line.line);
+                        for fall_through in fall_throughs {
+                            self.add_edge(fall_through, Target::Node(after));
+                        }
+                        cursor = Some(Cursor {
+                            from: after,
+                            kind: EdgeKind::FallThrough,
+                            line: line.line,
+                            dialogue: Vec::new(),
+                        });
+                    }
+                    index = menu_end;
+                    continue;
+                }
+                _ => (),
+            }
+            index += 1;
+        }
+        cursor
+    }
+
+    fn mark_reachable(&mut self, start: usize) {
+        let mut queue = VecDeque::from([start]);
+        while let Some(node) = queue.pop_front() {
+            if self.nodes[node].reachable {
+                continue;
+            }
+            self.nodes[node].reachable = true;
+            for edge in self.edges.iter().filter(|edge| edge.from == node) {
+                if let Target::Node(to) = edge.to {
+                    queue.push_back(to);
+                }
+            }
+        }
+    }
+}
+
+/// How much searching `ending_paths` does per path it may return, so a
+/// tangle of loops with no way out still ends.
+const STEPS_PER_PATH: usize = 100;
+
+struct PathSearch<'a> {
+    graph: &'a FlowGraph,
+    /// The edges worth taking from each node, with the node they go to.
+    outgoing: Vec<Vec<(usize, usize)>>,
+    path: Vec<usize>,
+    /// Which edges the current path takes.
+    taken: Vec<bool>,
+    paths: Vec<Vec<usize>>,
+    steps: usize,
+    limit: usize,
+}
+
+impl PathSearch<'_> {
+    fn visit(&mut self, node: usize) {
+        self.steps += 1;
+        if self.paths.len() >= self.limit || self.steps >= self.limit * STEPS_PER_PATH {
+            return;
+        }
+        if self.graph.nodes[node].kind == NodeKind::End {
+            self.paths.push(self.path.clone());
+            return;
+        }
+        for step in 0..self.outgoing[node].len() {
+            let (index, to) = self.outgoing[node][step];
+            if self.taken[index] {
+                continue;
+            }
+            self.taken[index] = true;
+            self.path.push(index);
+            self.visit(to);
+            self.path.pop();
+            self.taken[index] = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(script: &str) -> FlowGraph {
+        let lines = crate::parse_test_script(script);
+        FlowGraph::build(&lines)
+    }
+
+    /// The choices each path makes, in order.
+    fn choices(graph: &FlowGraph, paths: &[Vec<usize>]) -> Vec<Vec<String>> {
+        paths
+            .iter()
+            .map(|path| {
+                path.iter()
+                    .filter_map(|edge| match &graph.edges[*edge].kind {
+                        EdgeKind::Choice { text, .. } => Some(text.clone()),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_every_ending() {
+        let graph = graph(
+            "label start:\n    menu:\n        \"Left\":\n            \"A\"\n        \"Right\":\n            jump right\n    return\nlabel right:\n    \"B\"\n    return\n",
+        );
+        let (paths, stopped) = graph.ending_paths(10);
+        assert!(!stopped);
+        let mut choices = choices(&graph, &paths);
+        choices.sort();
+        assert_eq!(choices, [["Left"], ["Right"]]);
+    }
+
+    #[test]
+    fn follows_a_loop_once() {
+        let graph = graph(
+            "label start:\n    menu pick:\n        \"Again\":\n            jump pick\n        \"Leave\":\n            return\n",
+        );
+        let (paths, stopped) = graph.ending_paths(10);
+        assert!(!stopped);
+        let mut choices = choices(&graph, &paths);
+        choices.sort();
+        assert_eq!(choices, [vec!["Again", "Leave"], vec!["Leave"]]);
+
+        let (paths, stopped) = graph.ending_paths(1);
+        assert!(stopped);
+        assert_eq!(paths.len(), 1);
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+
+use serde::Deserialize;
+
+use crate::backend::block_end;
+use crate::graph::{EdgeKind, FlowGraph, NodeKind, Target};
+use crate::{ParseLogicalLine, ParseStatement, Speaker};
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Off,
+    Warning,
+    Error,
+}
+
+/// Names accepted on the right of `--severity RULE=LEVEL`.
+pub const SEVERITY_NAMES: [&str; 3] = ["off", "warning", "error"];
+
+impl Severity {
+    pub fn by_name(name: &str) -> Option<Severity> {
+        match name {
+            "off" => Some(Severity::Off),
+            "warning" => Some(Severity::Warning),
+            "error" => Some(Severity::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Every rule with the severity it has unless `--severity` says otherwise.
+pub const RULES: [(&str, Severity); 6] = [
+    ("undefined-label", Severity::Error),
+    ("unreachable-label", Severity::Warning),
+    ("empty-menu", Severity::Error),
+    ("choice-without-target", Severity::Warning),
+    ("unused-character", Severity::Warning),
+    ("duplicate-label", Severity::Error),
+];
+
+pub struct Finding {
+    pub line: usize,
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Checks the script for structural problems, returning the findings whose
+/// rule isn't turned off, in line order.
+pub fn lint(lines: &[ParseLogicalLine], severities: &[(String, Severity)]) -> Vec<Finding> {
+    let graph = FlowGraph::build(lines);
+    let mut findings = Vec::new();
+    let mut report = |line: usize, rule: &'static str, message: String| {
+        let severity = severities
+            .iter()
+            .rev()
+            .find(|(name, _)| name == rule)
+            .map(|(_, severity)| *severity)
+            .or(RULES
+                .iter()
+                .find(|(name, _)| *name == rule)
+                .map(|(_, severity)| *severity))
+            .unwrap();
+        if severity != Severity::Off {
+            findings.push(Finding {
+                line,
+                rule,
+                severity,
+                message,
+            });
+        }
+    };
+
+    for edge in &graph.edges {
+        if let Target::Missing(key) = &edge.to {
+            let statement = match edge.kind {
+                EdgeKind::Call => "call",
+                _ => "jump",
+            };
+            report(
+                edge.line,
+                "undefined-label",
+                format!("{} to undefined label `{}`", statement, key),
+            );
+        }
+    }
+
+    let mut labels = HashSet::new();
+    for node in &graph.nodes {
+        match &node.kind {
+            NodeKind::Label { key } if !labels.insert(key) => {
+                report(
+                    node.line,
+                    "duplicate-label",
+                    format!("label `{}` is already defined", key),
+                );
+            }
+            NodeKind::Label { key } if !node.reachable => {
+                report(
+                    node.line,
+                    "unreachable-label",
+                    format!("label `{}` can't be reached from `start`", key),
+                );
+            }
+            _ => (),
+        }
+    }
+
+    for (index, node) in graph.nodes.iter().enumerate() {
+        if !matches!(node.kind, NodeKind::Menu { .. }) {
+            continue;
+        }
+        let choices: Vec<_> = graph
+            .edges
+            .iter()
+            .filter(|edge| edge.from == index)
+            .collect();
+        if choices.is_empty() {
+            report(node.line, "empty-menu", "menu has no choices".to_string());
+        }
+        for choice in choices {
+            let EdgeKind::Choice { text, .. } = &choice.kind else {
+                continue;
+            };
+            // A body that only says things and carries on after the menu
+            // is fine; one with nothing in it (just `pass`) was likely left
+            // unfinished.
+            let lands_after_menu = matches!(
+                choice.to,
+                Target::Node(to) if graph.nodes[to].kind == NodeKind::AfterMenu
+            );
+            let body_is_empty = lines
+                .iter()
+                .position(|line| line.line == choice.line)
+                .is_some_and(|index| block_end(lines, index) == index + 1);
+            if lands_after_menu && body_is_empty {
+                report(
+                    choice.line,
+                    "choice-without-target",
+                    format!("choice `{}` does nothing", text),
+                );
+            }
+        }
+    }
+
+    let mut speakers = HashSet::new();
+    for line in lines {
+        if let ParseStatement::Dialogue {
+            speaker: Speaker::Character(key),
+            ..
+        } = &line.statement
+        {
+            speakers.insert(key.as_str());
+        }
+    }
+    for line in lines {
+        if let ParseStatement::Definition { key, .. } = &line.statement {
+            if !speakers.contains(key.as_str()) {
+                report(
+                    line.line,
+                    "unused-character",
+                    format!("character `{}` is never used", key),
+                );
+            }
+        }
+    }
+
+    findings.sort_by_key(|finding| finding.line);
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(script: &str) -> Vec<&'static str> {
+        let lines = crate::parse_test_script(script);
+        lint(&lines, &[])
+            .iter()
+            .map(|finding| finding.rule)
+            .collect()
+    }
+
+    #[test]
+    fn flags_only_empty_choices() {
+        let script = "label start:\n    menu:\n        \"Stay\":\n            \"Fine.\"\n        \"Nothing\":\n            pass\n    \"After.\"\n";
+        assert_eq!(rules(script), ["choice-without-target"]);
+    }
+
+    #[test]
+    fn flags_label_problems() {
+        let script =
+            "label start:\n    jump nowhere\nlabel lost:\n    \"A\"\nlabel lost:\n    \"B\"\n";
+        assert_eq!(
+            rules(script),
+            ["undefined-label", "unreachable-label", "duplicate-label"]
+        );
+    }
+
+    #[test]
+    fn severities_override_rules() {
+        let lines = crate::parse_test_script("label start:\n    jump nowhere\n");
+        let findings = lint(
+            &lines,
+            &[("undefined-label".to_string(), Severity::Warning)],
+        );
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert!(lint(&lines, &[("undefined-label".to_string(), Severity::Off)]).is_empty());
+    }
+}
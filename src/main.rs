@@ -1,113 +1,420 @@
-use std::{collections::HashMap, fs};
+mod backend;
+mod config;
+mod graph;
+mod lint;
+mod metadata;
+mod paths;
+mod stats;
+mod transform;
 
-#[derive(Debug, Clone)]
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use metadata::Metadata;
+use serde::Serialize;
+use transform::{Placement, TransformTable};
+
+#[derive(Debug, Clone, Serialize)]
 struct ParseLogicalLine {
+    #[serde(skip)]
+    line: usize,
     indent: usize,
     statement: ParseStatement,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 enum ParseStatement {
-    Definition { key: String, character: Character },
-    Label { key: String },
-    Dialogue { character_key: String, text: String },
-    Menu {},
-    Choice { text: String },
-    Jump { key: String },
+    Definition {
+        key: String,
+        character: Character,
+    },
+    Label {
+        key: String,
+    },
+    Dialogue {
+        speaker: Speaker,
+        text: String,
+    },
+    Menu {
+        /// `menu name:` makes the menu a jump target, like a label.
+        name: Option<String>,
+    },
+    Choice {
+        text: String,
+        /// The expression after `if`, for choices only shown when it holds.
+        condition: Option<String>,
+    },
+    /// A menu's `set` clause: choices already picked, recorded in the named
+    /// set, aren't offered again.
+    MenuSet {
+        name: String,
+    },
+    Jump {
+        key: String,
+    },
+    Call {
+        key: String,
+    },
     End {},
-    Show { key: String, location: Location },
-    StageDirection { location: Location },
-    Scene { key: String},
+    Show {
+        key: String,
+        placement: Placement,
+        zorder: Option<i32>,
+    },
+    StageDirection {
+        placement: Placement,
+    },
+    Hide {
+        key: String,
+    },
+    Scene {
+        key: String,
+    },
+    Default {
+        name: String,
+        value: String,
+    },
 }
 
-#[derive(Debug, Clone)]
-enum Location {
-    Left,
-    CenterLeft,
-    Center,
-    CenterRight,
-    Right,
-    Off,
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", content = "name", rename_all = "snake_case")]
+enum Speaker {
+    Narrator,
+    Character(String),
+    Inline(String),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 struct Character {
     name: String,
     color: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 struct Page {
-    index: usize,
-    label: Option<String>,
+    /// Source line of the statement the page was made from.
+    #[serde(skip)]
+    line: usize,
+    /// Labels that start at this page. Labels written one after another, or
+    /// one that only jumps on to another, share the page they lead to.
+    labels: Vec<String>,
+    /// Other places links land at this page: the start of a choice's body,
+    /// or where play carries on after a menu. They are named after the
+    /// source line, e.g. `line-42`, which no label can be.
+    anchors: Vec<String>,
     text: PageText,
-    character_images: [Option<String>; 5],
-    background: Option<String>,
+    stage: Stage,
     unconditional_jump: Option<String>,
     end: bool,
 }
 
-#[derive(Debug, Clone)]
+impl Page {
+    /// Every name a link can use to reach this page.
+    fn targets(&self) -> impl Iterator<Item = &String> {
+        self.labels.iter().chain(&self.anchors)
+    }
+}
+
+/// Everything on screen behind the text box. Every page carries the stage
+/// as play left it, whatever kind of page it is.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+struct Stage {
+    background: Option<String>,
+    sprites: Vec<Sprite>,
+}
+
+/// A character image on stage, kept in the order it was shown.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Sprite {
+    image: String,
+    /// Where the anchor goes, as a fraction of the screen width.
+    xpos: f32,
+    /// Which point of the sprite goes at `xpos`, as a fraction of its width.
+    xanchor: f32,
+    zorder: i32,
+    zoom: f32,
+}
+
+impl Sprite {
+    /// The first word of the image name, which Ren'Py uses to decide which
+    /// sprite a new `show` replaces.
+    fn tag(&self) -> &str {
+        self.image.split(' ').next().unwrap()
+    }
+
+    /// The left edge of a sprite `width` wide, both as fractions of the
+    /// screen width.
+    fn left(&self, width: f32) -> f32 {
+        self.xpos - self.xanchor * width
+    }
+
+    /// Whether the sprite is moved fully off screen, whatever its width.
+    fn is_off_screen(&self) -> bool {
+        self.xpos <= (self.xanchor - 1.0).min(0.0) || self.xpos >= (self.xanchor + 1.0).max(1.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 enum PageText {
     Dialogue {
-        character_name: String,
+        character: Option<Character>,
         text: String,
     },
     Menu {
-        character_name: String,
+        character: Option<Character>,
         text: String,
         choices: Vec<MenuChoice>,
     },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 struct MenuChoice {
     text: String,
     jump_key: String,
+    /// Shown as a note on the choice, since variables aren't tracked.
+    condition: Option<String>,
 }
 
-fn main() {
-    let script = std::fs::read_to_string("input/script09_cut.rpy").unwrap();
-    let mut logical_lines: Vec<ParseLogicalLine> = Vec::<ParseLogicalLine>::new();
-    let mut look_for_keys = Vec::<String>::new();
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    /// Write the pages out with the chosen backend.
+    Render,
+    /// Report structural problems in the script instead.
+    Lint,
+    /// Report word and line counts.
+    Stats,
+    /// List the playthroughs from `start` to each ending.
+    Paths,
+}
 
-    logical_lines.push(ParseLogicalLine {
-        indent: 0,
-        statement: ParseStatement::Definition {
-            key: "".to_string(),
-            character: Character {
-                name: "".to_string(),
-                color: "".to_string(),
-            },
-        },
-    });
+#[derive(Debug, Clone)]
+struct Options {
+    command: Command,
+    input: String,
+    output: String,
+    /// Where the images are read from, `images` in the output directory
+    /// if unset.
+    images: Option<String>,
+    screen_width: f32,
+    strict: bool,
+    transforms: Vec<(String, f32)>,
+    backend: String,
+    theme: String,
+    theme_options: Vec<String>,
+    collapse_dialogue: bool,
+    twine_format: String,
+    font: Option<String>,
+    severities: Vec<(String, lint::Severity)>,
+    /// Overrides for what the script and `rentex.toml` say about the game.
+    metadata: Metadata,
+    cover: bool,
+}
 
-    for line in script.lines() {
-        match parse_line(line.to_string(), &mut look_for_keys) {
-            Ok(logical_line) => logical_lines.push(logical_line),
-            Err(_) => (println!("Invalid line: {}", line)),
-        }
-        if logical_lines.len() > 2 {
-            let last_line = logical_lines.last().unwrap();
-            let two_index = logical_lines.len() - 2;
-            match &last_line.statement {
-                ParseStatement::StageDirection { location } => {
-                    let statement = logical_lines.get(two_index).unwrap().statement.clone();
-                    match statement {
-                        ParseStatement::Show { key, location: _ } => {
-                            logical_lines.get_mut(two_index).unwrap().statement =
-                                ParseStatement::Show {
-                                    key: key,
-                                    location: location.clone(),
-                                };
-                        }
-                        _ => (),
-                    }
-                    logical_lines.pop();
+/// Reads the command line on top of the settings from `rentex.toml`.
+fn parse_args(config: &config::Config) -> Result<Options, String> {
+    let mut options = Options {
+        command: Command::Render,
+        input: config
+            .input
+            .clone()
+            .unwrap_or("input/script09_cut.rpy".to_string()),
+        output: config.output.clone().unwrap_or("output".to_string()),
+        images: config.images.clone(),
+        screen_width: config.screen_width.unwrap_or(transform::SCREEN_WIDTH),
+        strict: config.strict.unwrap_or(false),
+        transforms: config
+            .transforms
+            .iter()
+            .map(|(name, xalign)| (name.clone(), *xalign))
+            .collect(),
+        backend: config.backend.clone().unwrap_or("beamer".to_string()),
+        theme: config.theme.clone().unwrap_or("rentex".to_string()),
+        theme_options: config.theme_options.clone(),
+        collapse_dialogue: config.collapse_dialogue.unwrap_or(false),
+        twine_format: config
+            .twine_format
+            .clone()
+            .unwrap_or("sugarcube".to_string()),
+        font: config.font.clone(),
+        severities: config
+            .severity
+            .iter()
+            .map(|(rule, severity)| (rule.clone(), *severity))
+            .collect(),
+        metadata: Metadata::default(),
+        cover: config.metadata.cover.unwrap_or(false),
+    };
+    let mut args = std::env::args().skip(1).peekable();
+    match args.peek().map(|arg| arg.as_str()) {
+        Some("lint") => options.command = Command::Lint,
+        Some("stats") => options.command = Command::Stats,
+        Some("paths") => options.command = Command::Paths,
+        _ => (),
+    }
+    if options.command != Command::Render {
+        args.next();
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strict" => options.strict = true,
+            "--output" | "-o" => options.output = args.next().ok_or("--output needs a DIR")?,
+            "--images" => options.images = Some(args.next().ok_or("--images needs a DIR")?),
+            "--screen-width" => {
+                let value = args.next().ok_or("--screen-width needs PIXELS")?;
+                options.screen_width = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|width| *width > 0.0)
+                    .ok_or(format!("Invalid screen width `{}`", value))?;
+            }
+            "--transform" => {
+                let value = args.next().ok_or("--transform needs NAME=XALIGN")?;
+                let (name, xalign) = value
+                    .split_once("=")
+                    .and_then(|(name, xalign)| Some((name, xalign.parse::<f32>().ok()?)))
+                    .ok_or(format!(
+                        "Invalid transform `{}`, expected NAME=XALIGN",
+                        value
+                    ))?;
+                options.transforms.push((name.to_string(), xalign));
+            }
+            "--backend" | "-b" => options.backend = args.next().ok_or("--backend needs a NAME")?,
+            "--theme" => options.theme = args.next().ok_or("--theme needs a NAME")?,
+            "--theme-option" => {
+                let value = args.next().ok_or("--theme-option needs KEY=VALUE")?;
+                options.theme_options.push(value);
+            }
+            "--collapse-dialogue" => options.collapse_dialogue = true,
+            "--twine-format" => {
+                let value = args.next().ok_or("--twine-format needs a FORMAT")?;
+                if !backend::STORY_FORMAT_NAMES.contains(&value.as_str()) {
+                    return Err(format!(
+                        "Unknown Twine story format `{}`, expected one of: {}",
+                        value,
+                        backend::STORY_FORMAT_NAMES.join(", ")
+                    ));
+                }
+                options.twine_format = value;
+            }
+            "--font" => options.font = Some(args.next().ok_or("--font needs a PATH")?),
+            "--severity" => {
+                let value = args.next().ok_or("--severity needs RULE=LEVEL")?;
+                let (rule, severity) = value
+                    .split_once("=")
+                    .and_then(|(rule, level)| Some((rule, lint::Severity::by_name(level)?)))
+                    .ok_or(format!(
+                        "Invalid severity `{}`, expected RULE={}",
+                        value,
+                        lint::SEVERITY_NAMES.join("|")
+                    ))?;
+                if !lint::RULES.iter().any(|(name, _)| *name == rule) {
+                    let names: Vec<&str> = lint::RULES.iter().map(|(name, _)| *name).collect();
+                    return Err(format!(
+                        "Unknown lint rule `{}`, expected one of: {}",
+                        rule,
+                        names.join(", ")
+                    ));
                 }
-                _ => (),
+                options.severities.push((rule.to_string(), severity));
+            }
+            "--title" => options.metadata.title = Some(args.next().ok_or("--title needs a TITLE")?),
+            "--author" => {
+                options.metadata.author = Some(args.next().ok_or("--author needs an AUTHOR")?)
+            }
+            "--game-version" => {
+                options.metadata.version =
+                    Some(args.next().ok_or("--game-version needs a VERSION")?)
             }
+            "--date" => options.metadata.date = Some(args.next().ok_or("--date needs a DATE")?),
+            "--cover" => options.cover = true,
+            "--cover-image" => {
+                options.metadata.cover = Some(args.next().ok_or("--cover-image needs a PATH")?)
+            }
+            _ if arg.starts_with("-") => return Err(format!("Unknown option: {}", arg)),
+            _ => options.input = arg,
+        }
+    }
+    Ok(options)
+}
+
+fn main() {
+    let config = match config::load_config() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let options = match parse_args(&config) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}", error);
+            eprintln!(
+                "Usage: rentex [lint|stats|paths] [--strict] [--output DIR] [--images DIR] [--screen-width PIXELS] [--backend NAME] [--transform NAME=XALIGN]... [--theme NAME] [--theme-option KEY=VALUE]... [--collapse-dialogue] [--twine-format FORMAT] [--font PATH] [--severity RULE=LEVEL]... [--title TITLE] [--author AUTHOR] [--game-version VERSION] [--date DATE] [--cover] [--cover-image PATH] [input.rpy]"
+            );
+            std::process::exit(2);
+        }
+    };
+    let Some(output_backend) = backend::backend_by_name(&options.backend, &options) else {
+        eprintln!(
+            "Unknown backend `{}`, expected one of: {}",
+            options.backend,
+            backend::BACKEND_NAMES.join(", ")
+        );
+        std::process::exit(2);
+    };
+    let script = match std::fs::read_to_string(&options.input) {
+        Ok(script) => script,
+        Err(error) => {
+            eprintln!("Error: {}: {}", options.input, error);
+            std::process::exit(1);
+        }
+    };
+
+    let mut transforms = transform::default_transforms();
+    transform::collect_transforms(&script, &mut transforms, options.screen_width);
+    for (name, xalign) in &options.transforms {
+        let placement = Placement::align(*xalign);
+        transforms.insert(name.clone(), placement);
+    }
+
+    let logical_lines = parse_script(&script, &transforms, options.screen_width);
+    if let Err(error) = check_speakers(&logical_lines, options.strict) {
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+
+    if options.command == Command::Stats {
+        print!("{}", stats::report(&logical_lines));
+        return;
+    }
+    if options.command == Command::Paths {
+        print!("{}", paths::report(&logical_lines));
+        return;
+    }
+    if options.command == Command::Lint {
+        let findings = lint::lint(&logical_lines, &options.severities);
+        let mut errors = 0;
+        for finding in &findings {
+            let severity = match finding.severity {
+                lint::Severity::Error => {
+                    errors += 1;
+                    "Error"
+                }
+                _ => "Warning",
+            };
+            println!(
+                "{}: line {}: {} [{}]",
+                severity, finding.line, finding.message, finding.rule
+            );
         }
+        println!(
+            "{} error(s), {} warning(s)",
+            errors,
+            findings.len() - errors
+        );
+        std::process::exit(if errors > 0 { 1 } else { 0 });
     }
 
     for line in logical_lines.clone() {
@@ -115,128 +422,423 @@ fn main() {
         print!("{}", " ".repeat(line.indent));
         match statement {
             ParseStatement::Definition { key, character } => {
-                println!("define {}: {}", key, character.name);
+                println!("define {}: {} ({})", key, character.name, character.color);
             }
             ParseStatement::Label { key } => {
                 println!("Label: {}", key);
             }
-            ParseStatement::Dialogue {
-                character_key,
-                text,
-            } => {
-                println!("{}: {}", character_key, text);
-            }
-            ParseStatement::Menu {} => {
-                println!("Menu");
-            }
-            ParseStatement::Choice { text } => {
-                println!("Choice: {}", text);
+            ParseStatement::Dialogue { speaker, text } => match speaker {
+                Speaker::Narrator => println!("{}", text),
+                Speaker::Character(key) => println!("{}: {}", key, text),
+                Speaker::Inline(name) => println!("\"{}\": {}", name, text),
+            },
+            ParseStatement::Menu { name } => match name {
+                Some(name) => println!("Menu: {}", name),
+                None => println!("Menu"),
+            },
+            ParseStatement::Choice { text, condition } => match condition {
+                Some(condition) => println!("Choice: {} if {}", text, condition),
+                None => println!("Choice: {}", text),
+            },
+            ParseStatement::MenuSet { name } => {
+                println!("Set: {}", name);
             }
             ParseStatement::Jump { key } => {
                 println!("Jump: {}", key);
             }
+            ParseStatement::Call { key } => {
+                println!("Call: {}", key);
+            }
             ParseStatement::End {} => {
                 println!("End");
             }
-            ParseStatement::Show { key, location } => {
-                println!("Show: {} at {:?}", key, location);
+            ParseStatement::Show {
+                key,
+                placement,
+                zorder,
+            } => {
+                println!("Show: {} at {:?} zorder {:?}", key, placement, zorder);
+            }
+            ParseStatement::StageDirection { placement } => {
+                println!("Stage Direction: {:?}", placement);
             }
-            ParseStatement::StageDirection { location } => {
-                println!("Stage Direction: {:?}", location);
+            ParseStatement::Hide { key } => {
+                println!("Hide: {}", key);
             }
             ParseStatement::Scene { key } => {
                 println!("Scene: {}", key);
             }
+            ParseStatement::Default { name, value } => {
+                println!("Default: {} = {}", name, value);
+            }
         }
     }
 
-    let pages = traverse_game(logical_lines);
+    let pages = if output_backend.uses_pages() {
+        match traverse_game(logical_lines.clone()) {
+            Ok(pages) => pages,
+            Err(error) => {
+                eprintln!("Error: {}", error);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        Vec::new()
+    };
 
     println!("Pages: {:#?}", pages);
 
-    let latex = latex_output(pages);
+    let mut metadata = metadata::collect_metadata(&script, &options.input)
+        .merge(config.metadata.metadata())
+        .merge(options.metadata.clone());
+    if !options.cover {
+        metadata.cover = None;
+    } else if let Some(cover) = &metadata.cover {
+        if !metadata::is_cover_image(cover) {
+            eprintln!(
+                "Warning: cover image `{}` is not a PNG or JPEG, cover page left out",
+                cover
+            );
+            metadata.cover = None;
+        } else if !std::fs::exists(cover).unwrap_or(false) {
+            eprintln!(
+                "Warning: cover image `{}` not found, cover page left out",
+                cover
+            );
+            metadata.cover = None;
+        }
+    } else {
+        eprintln!("Warning: no `gui.main_menu_background` for the cover, pass --cover-image PATH");
+    }
 
-    std::fs::write("output/out.tex", latex).unwrap();
+    let document = backend::Document {
+        source: options.input.clone(),
+        metadata,
+        images: backend::Images::new(
+            &options
+                .images
+                .clone()
+                .unwrap_or(format!("{}/images", options.output)),
+            &options.output,
+            options.screen_width,
+        ),
+        lines: logical_lines,
+        pages,
+    };
+    let files = match output_backend.render(&document) {
+        Ok(files) => files,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+    };
+    if let Err(error) = std::fs::create_dir_all(&options.output) {
+        eprintln!("Error: {}: {}", options.output, error);
+        std::process::exit(1);
+    }
+    for file in files {
+        let path = format!("{}/{}", options.output, file.path);
+        if let Err(error) = std::fs::write(&path, file.contents) {
+            eprintln!("Error: {}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_script(
+    script: &str,
+    transforms: &TransformTable,
+    screen_width: f32,
+) -> Vec<ParseLogicalLine> {
+    let mut logical_lines: Vec<ParseLogicalLine> = Vec::<ParseLogicalLine>::new();
+    let mut look_for_keys = Vec::<String>::new();
+    // Indent of the block being skipped, if any.
+    let mut skipped_indent = None;
+
+    for (index, line) in script.lines().enumerate() {
+        let indent = line.len() - line.trim_start().len();
+        if skipped_indent.is_some_and(|skipped| line.trim().is_empty() || indent > skipped) {
+            continue;
+        }
+        skipped_indent = None;
+        if is_skipped_block(line) {
+            skipped_indent = Some(indent);
+            continue;
+        }
+        match parse_line(
+            line.to_string(),
+            index + 1,
+            &mut look_for_keys,
+            transforms,
+            screen_width,
+        ) {
+            Ok(logical_line) => logical_lines.push(logical_line),
+            // Blank lines, comments, `pass` and defines other than
+            // characters are expected to be skipped.
+            Err(_) if is_skipped_line(line) => (),
+            Err(_) => eprintln!("Invalid line {}: {}", index + 1, line),
+        }
+        extend_dialogue(&mut logical_lines);
+        if logical_lines.len() > 2 {
+            let last_line = logical_lines.last().unwrap();
+            let two_index = logical_lines.len() - 2;
+            if let ParseStatement::StageDirection {
+                placement: direction,
+            } = &last_line.statement
+            {
+                let statement = logical_lines.get(two_index).unwrap().statement.clone();
+                if let ParseStatement::Show {
+                    key,
+                    placement,
+                    zorder,
+                } = statement
+                {
+                    logical_lines.get_mut(two_index).unwrap().statement = ParseStatement::Show {
+                        key,
+                        placement: placement.merge(*direction),
+                        zorder,
+                    };
+                }
+                logical_lines.pop();
+            }
+        }
+    }
+
+    logical_lines
+}
+
+/// Whether a line starts a block that isn't part of the story. Translations
+/// repeat the script's lines in another language, and string translations
+/// are `old` and `new` pairs. Transforms were already read into the
+/// transform table, and their ATL would otherwise look like stage
+/// directions. Screens, styles and Python blocks have lines such as
+/// `text "Hello"` or `font "DejaVuSans.ttf"` that look like say statements.
+fn is_skipped_block(line: &str) -> bool {
+    let mut words = line.trim().trim_end_matches(":").split_whitespace();
+    match words.next() {
+        Some("translate" | "transform" | "screen" | "style" | "python") => true,
+        Some("init") => words.any(|word| word == "python"),
+        _ => false,
+    }
+}
+
+/// Whether a line is empty, a comment, `pass` or a `define`, none of which
+/// are part of the story. Character defines are parsed before this is asked,
+/// and the rest set up config, positions or metadata read elsewhere.
+fn is_skipped_line(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || line.starts_with('#') || line == "pass" || line.starts_with("define ")
+}
+
+/// Folds a say statement by `extend` into the say statement right before
+/// it, which Ren'Py shows as one line. With a stage change in between, the
+/// text stays a line of its own after the change, said by the same speaker.
+/// With nothing to extend, the text is narration.
+fn extend_dialogue(logical_lines: &mut Vec<ParseLogicalLine>) {
+    let Some(ParseLogicalLine {
+        statement:
+            ParseStatement::Dialogue {
+                speaker: Speaker::Character(key),
+                text,
+            },
+        ..
+    }) = logical_lines.last()
+    else {
+        return;
+    };
+    if key != EXTEND_SPEAKER {
+        return;
+    }
+    let text = text.clone();
+    let last = logical_lines.len() - 1;
+    let previous = logical_lines[..last]
+        .iter_mut()
+        .enumerate()
+        .rev()
+        .find(|(_, line)| {
+            !matches!(
+                line.statement,
+                ParseStatement::Show { .. }
+                    | ParseStatement::Hide { .. }
+                    | ParseStatement::Scene { .. }
+            )
+        });
+    let speaker = match previous {
+        Some((
+            index,
+            ParseLogicalLine {
+                statement: ParseStatement::Dialogue { text: previous, .. },
+                ..
+            },
+        )) if index + 1 == last => {
+            *previous += &text;
+            logical_lines.pop();
+            return;
+        }
+        Some((
+            _,
+            ParseLogicalLine {
+                statement: ParseStatement::Dialogue { speaker, .. },
+                ..
+            },
+        )) => speaker.clone(),
+        _ => Speaker::Narrator,
+    };
+    if let ParseStatement::Dialogue {
+        speaker: extended, ..
+    } = &mut logical_lines[last].statement
+    {
+        *extended = speaker;
+    }
 }
 
 fn parse_line(
     line: String,
+    line_number: usize,
     look_for_keys: &mut Vec<String>,
+    transforms: &TransformTable,
+    screen_width: f32,
 ) -> Result<ParseLogicalLine, &'static str> {
     let line_trim = line.trim();
     if line_trim.starts_with("define") && line_trim.contains("Character") {
-        let line_new = line_trim.replace("define", "");
-        let line_split: Vec<String> = line_new.split("=").map(|x| x.to_string()).collect();
-
-        let key = line_split[0].trim().to_string();
+        let line_new = line_trim.replacen("define", "", 1);
+        let (key, value) = line_new.split_once("=").ok_or("Invalid definition")?;
+        let key = key.trim().to_string();
 
-        println!("{}", line);
-
-        let name_first_quote = line_split[1].find("\"").unwrap();
-        let name_last_quote = line_split[1].rfind("\"").unwrap();
-        let name = line_split[1][name_first_quote + 1..name_last_quote].to_string();
-
-        /*let color = if line_trim.contains("color") {
-            let color_first_quote = line_split[2].find("\"").unwrap();
-            let color_last_quote = line_split[2].rfind("\"").unwrap();
-            line_split[2][color_first_quote + 1..color_last_quote].to_string()
-        } else {
-            "".to_string()
-        };*/
-        
-        let color = "".to_string();
-
-        let character = Character {
-            name: name,
-            color: color,
+        // Example line
+        // define e = Character(_("Eileen"), color="#c8ffc8", image="eileen")
+        let arguments = match (value.find("("), value.rfind(")")) {
+            (Some(open), Some(close)) if open < close => &value[open + 1..close],
+            _ => "",
         };
+        let name = split_arguments(arguments)
+            .first()
+            .filter(|first| !first.contains("="))
+            .and_then(|first| first.find(['"', '\'']).map(|quote| &first[quote..]))
+            .and_then(take_string_literal)
+            .map(|(name, _)| clean_up_text(name))
+            .unwrap_or_default();
+        let color = keyword_argument(arguments, "color")
+            .and_then(take_string_literal)
+            .map(|(color, _)| color)
+            .unwrap_or_default();
+
+        let character = Character { name, color };
 
         look_for_keys.push(key.clone());
         return Ok(ParseLogicalLine {
+            line: line_number,
             indent: line.find("define").unwrap(),
-            statement: ParseStatement::Definition {
-                key: key,
-                character: character,
-            },
+            statement: ParseStatement::Definition { key, character },
         });
     } else if line_trim.starts_with("label") {
         let line_new = line_trim.replace("label", "").trim().to_string();
         let key = line_new.replace(":", "").trim().to_string();
         return Ok(ParseLogicalLine {
+            line: line_number,
             indent: line.find("label").unwrap(),
-            statement: ParseStatement::Label { key: key },
+            statement: ParseStatement::Label { key },
+        });
+    } else if line_trim.starts_with("default ") {
+        // Example line
+        // default affection = 0
+        let line_new = line_trim.replacen("default", "", 1);
+        let (name, value) = line_new.split_once("=").ok_or("Invalid default")?;
+        return Ok(ParseLogicalLine {
+            line: line_number,
+            indent: line.find("default").unwrap(),
+            statement: ParseStatement::Default {
+                name: name.trim().to_string(),
+                value: value.trim().to_string(),
+            },
         });
     } else if line_trim.starts_with("\"") {
-        let text = line_trim.trim().to_string();
-        if line.ends_with(":") {
+        let (text, rest) = take_string_literal(line_trim).ok_or("Unterminated string")?;
+        let indent = line.find("\"").unwrap();
+        // Example line
+        // "Stranger" "Who are you?"
+        if let Some((inline_text, _)) = take_string_literal(rest) {
             return Ok(ParseLogicalLine {
-                indent: line.find("\"").unwrap(),
+                line: line_number,
+                indent,
+                statement: ParseStatement::Dialogue {
+                    speaker: Speaker::Inline(clean_up_text(text)),
+                    text: clean_up_text(inline_text),
+                },
+            });
+        }
+        if let Some(rest) = rest.trim_end().strip_suffix(":") {
+            // Example line
+            // "Kiss her" if affection > 5:
+            let condition = rest
+                .trim()
+                .strip_prefix("if ")
+                .map(|condition| condition.trim().to_string());
+            return Ok(ParseLogicalLine {
+                line: line_number,
+                indent,
                 statement: ParseStatement::Choice {
                     text: clean_up_text(text),
+                    condition,
                 },
             });
         }
         return Ok(ParseLogicalLine {
-            indent: line.find("\"").unwrap(),
+            line: line_number,
+            indent,
             statement: ParseStatement::Dialogue {
-                character_key: "".to_string(),
+                speaker: Speaker::Narrator,
                 text: clean_up_text(text),
             },
         });
     } else if line_trim.starts_with("menu") {
+        // Example line
+        // menu chapter_choice:
+        let name = line_trim
+            .replacen("menu", "", 1)
+            .trim()
+            .trim_end_matches(":")
+            .trim()
+            .to_string();
         return Ok(ParseLogicalLine {
+            line: line_number,
             indent: line.find("menu").unwrap(),
-            statement: ParseStatement::Menu {},
+            statement: ParseStatement::Menu {
+                name: is_identifier(&name).then_some(name),
+            },
+        });
+    } else if line_trim.starts_with("set ") {
+        return Ok(ParseLogicalLine {
+            line: line_number,
+            indent: line.find("set").unwrap(),
+            statement: ParseStatement::MenuSet {
+                name: line_trim.replacen("set", "", 1).trim().to_string(),
+            },
         });
     } else if line_trim.starts_with("jump") {
         let line_new = line_trim.replace("jump", "").trim().to_string();
         let key = line_new.replace(":", "").trim().to_string();
         return Ok(ParseLogicalLine {
+            line: line_number,
             indent: line.find("jump").unwrap(),
-            statement: ParseStatement::Jump { key: key },
+            statement: ParseStatement::Jump { key },
+        });
+    } else if line_trim.starts_with("call ") && !line_trim.starts_with("call screen ") {
+        // Example line
+        // call chapter_two from _call_chapter_two
+        let key = line_trim["call ".len()..]
+            .split_whitespace()
+            .next()
+            .unwrap()
+            .replace(":", "");
+        return Ok(ParseLogicalLine {
+            line: line_number,
+            indent: line.find("call").unwrap(),
+            statement: ParseStatement::Call { key },
         });
     } else if line_trim.starts_with("return") {
         return Ok(ParseLogicalLine {
+            line: line_number,
             indent: line.find("return").unwrap(),
             statement: ParseStatement::End {},
         });
@@ -250,417 +852,1000 @@ fn parse_line(
         let last_quote = line_split[1].rfind("\"").unwrap();
         let text = line_split[1][first_quote + 1..last_quote].to_string();
         return Ok(ParseLogicalLine {
+            line: line_number,
             indent: line.find("$ speak").unwrap(),
             statement: ParseStatement::Dialogue {
-                character_key: key,
+                speaker: Speaker::Character(key),
                 text: clean_up_text(text),
             },
         });
     } else if line_trim.starts_with("show") {
-        let line_new = line_trim.replace("show", "").trim().to_string();
+        let line_new = line_trim.replacen("show", "", 1).trim().to_string();
         let line_split = line_new.split(" at ").collect::<Vec<&str>>();
-        let key = line_split[0]
+        let key = strip_show_clauses(line_split[0])
             .replace(":", "")
             .replace("flipped", "")
             .trim()
             .to_string();
-        let location = if (line_split.len() > 1) {
-            match line_split[1].trim() {
-                "left" => Location::Left,
-                "right" => Location::Right,
-                _ => Location::Center,
-            }
-        } else {
-            Location::Center
-        };
+        let placement = line_split
+            .get(1)
+            .map(|at| {
+                transform::transform_placement(
+                    strip_show_clauses(at).trim_end_matches(":"),
+                    transforms,
+                    screen_width,
+                )
+            })
+            .unwrap_or_default();
+        let zorder = line_new.split_once(" zorder ").and_then(|(_, rest)| {
+            let value = rest.split_whitespace().next()?;
+            value.trim_end_matches(":").parse::<i32>().ok()
+        });
         return Ok(ParseLogicalLine {
+            line: line_number,
             indent: line.find("show").unwrap(),
             statement: ParseStatement::Show {
-                key: key,
-                location: location,
+                key,
+                placement,
+                zorder,
             },
         });
+    } else if line_trim.starts_with("hide ") {
+        let line_new = line_trim.replacen("hide", "", 1).trim().to_string();
+        let key = strip_show_clauses(&line_new)
+            .replace(":", "")
+            .trim()
+            .to_string();
+        return Ok(ParseLogicalLine {
+            line: line_number,
+            indent: line.find("hide").unwrap(),
+            statement: ParseStatement::Hide { key },
+        });
     } else if line_trim.starts_with("scene") {
         let line_new = line_trim.replace("scene", "").trim().to_string();
         let key = line_new.replace(":", "");
         return Ok(ParseLogicalLine {
+            line: line_number,
             indent: line.find("scene").unwrap(),
-            statement: ParseStatement::Scene {
-                key: key,
-            },
+            statement: ParseStatement::Scene { key },
         });
-    } else if [
-        "leftstage",
-        "leftcenterstage",
-        "centerstage",
-        "rightcenterstage",
-        "rightstage",
-        "off_right",
-        "off_left",
-        "off_farright",
-        "off_farleft",
-        "percsuperleft",
-        "percrightcenter",
-        "xalign",
-    ]
-    .iter()
-    .any(|x| line.contains(x))
+    } else if let Some(placement) =
+        transform::stage_direction_placement(line_trim, transforms, screen_width)
     {
-        let location = if line_trim.contains("off") {
-            Location::Off
-        } else if line_trim.contains("leftcenterstage") {
-            Location::CenterLeft
-        } else if line_trim.contains("rightcenterstage") {
-            Location::CenterRight
-        } else if line_trim.contains("leftstage") {
-            Location::Left
-        } else if line_trim.contains("rightstage") {
-            Location::Right
-        } else if line_trim.contains("xalign") {
-            let xalign_index = line_trim.find("xalign").unwrap();
-            let next_word = line_trim[xalign_index + 6..].split(" ").nth(1).unwrap();
-            let xalign = next_word.parse::<f32>().unwrap();
-            if xalign < 0.33 {
-                Location::Left
-            } else if xalign < 0.66 {
-                Location::Center
-            } else {
-                Location::Right
-            }
-        } else {
-            Location::Center
-        };
         return Ok(ParseLogicalLine {
-            indent: 8,
-            statement: ParseStatement::StageDirection { location: location },
+            line: line_number,
+            indent: line.len() - line.trim_start().len(),
+            statement: ParseStatement::StageDirection { placement },
         });
     } else {
         for key in look_for_keys.iter() {
             if line_trim.starts_with(format!("{} ", key).as_str()) {
-                let text = line_trim
-                    .split(" ")
-                    .skip(1)
-                    .collect::<Vec<&str>>()
-                    .join(" ");
+                let rest = &line_trim[key.len()..];
+                let text = match rest
+                    .find("\"")
+                    .and_then(|quote| take_string_literal(&rest[quote..]))
+                {
+                    Some((text, _)) => text,
+                    None => line_trim
+                        .split(" ")
+                        .skip(1)
+                        .collect::<Vec<&str>>()
+                        .join(" "),
+                };
                 return Ok(ParseLogicalLine {
+                    line: line_number,
                     indent: line.find(key).unwrap(),
                     statement: ParseStatement::Dialogue {
-                        character_key: key.clone(),
+                        speaker: Speaker::Character(key.clone()),
                         text: clean_up_text(text),
                     },
                 });
             }
         }
+        // Say statements for characters defined in another file, e.g.
+        // eileen happy "Hello"
+        if let Some(quote) = line_trim.find("\"") {
+            let mut words = line_trim[..quote].split_whitespace();
+            if let Some(key) = words.next() {
+                if is_identifier(key)
+                    && !RENPY_KEYWORDS.contains(&key)
+                    && words.all(|word| {
+                        word.chars()
+                            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+                    })
+                {
+                    if let Some((text, _)) = take_string_literal(&line_trim[quote..]) {
+                        // `centered` is Ren'Py's speaker for narration shown
+                        // in the middle of the screen.
+                        let speaker = match key {
+                            "centered" => Speaker::Narrator,
+                            _ => Speaker::Character(key.to_string()),
+                        };
+                        return Ok(ParseLogicalLine {
+                            line: line_number,
+                            indent: line.find(key).unwrap(),
+                            statement: ParseStatement::Dialogue {
+                                speaker,
+                                text: clean_up_text(text),
+                            },
+                        });
+                    }
+                }
+            }
+        }
     }
-    return Err("Invalid line");
+    Err("Invalid line")
+}
+
+/// A label waiting to be rendered, with the stage as play left it.
+struct PendingLabel {
+    index: usize,
+    stage: Stage,
 }
 
-fn traverse_game(logical_lines: Vec<ParseLogicalLine>) -> Vec<Page> {
+fn traverse_game(logical_lines: Vec<ParseLogicalLine>) -> Result<Vec<Page>, String> {
     let mut pages = Vec::<Page>::new();
     let mut characters = HashMap::<String, Character>::new();
 
     // define characters
     for line in logical_lines.clone() {
-        let statement = &line.statement;
-        match statement {
-            ParseStatement::Definition { key, character } => {
-                characters.insert(key.clone(), character.clone());
+        if let ParseStatement::Definition { key, character } = &line.statement {
+            characters.insert(key.clone(), character.clone());
+        }
+    }
+
+    // find labels and named menus, keeping the first of any defined twice
+    let mut label_indices = HashMap::<&str, usize>::new();
+    for (index, line) in logical_lines.iter().enumerate() {
+        match &line.statement {
+            ParseStatement::Label { key }
+            | ParseStatement::Menu {
+                name: Some(key), ..
+            } => {
+                label_indices.entry(key).or_insert(index);
             }
             _ => (),
         }
     }
-
-    // find start label
-    let label_start_index = find_label_index(logical_lines.clone(), "start".to_string());
+    let label_start_index = *label_indices
+        .get("start")
+        .ok_or("no `label start` to begin at")?;
     println!("Start index: {}", label_start_index);
 
-    let mut current_index = label_start_index + 1;
-    let mut next_has_label = false;
-    let mut next_label = "".to_string();
-    let mut on_screen_characters = [None, None, None, None, None];
-    let mut current_background = None;
+    // Labels are rendered once each, starting from `start` and following
+    // jumps and choices. Anything that leads back to a rendered label links
+    // to it instead of repeating it, so loops in the script end.
+    let mut queue = VecDeque::from([PendingLabel {
+        index: label_start_index,
+        stage: Stage::default(),
+    }]);
+    let mut visited = HashSet::<usize>::new();
+    // Where play carries on after each menu, once it has left a choice.
+    let continuations: HashSet<usize> = logical_lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| matches!(line.statement, ParseStatement::Menu { .. }))
+        .map(|(index, _)| resume_index(&logical_lines, backend::block_end(&logical_lines, index)))
+        .collect();
+    // Labels and anchors that reach no page of their own, with the label
+    // or anchor they lead to.
+    let mut aliases = Vec::<(Anchor, String)>::new();
+    let mut stage = Stage::default();
 
     loop {
-        let line = &logical_lines[current_index];
-        let statement = &line.statement;
-        println!("Currently using: {:?}", statement);
-        println!("Currently showing: {:?}", on_screen_characters);
-        match statement {
-            ParseStatement::Dialogue {
-                character_key,
-                text,
-            } => {
-                let mut label = None;
-                if next_has_label {
-                    label = Some(next_label.clone());
-                    next_has_label = false;
+        let pending = match queue.pop_front() {
+            Some(pending) => pending,
+            // Labels nothing leads to are rendered afterwards, in file order.
+            None => match (0..logical_lines.len()).find(|index| {
+                matches!(
+                    logical_lines[*index].statement,
+                    ParseStatement::Label { .. }
+                ) && !visited.contains(index)
+            }) {
+                Some(index) => PendingLabel {
+                    index,
+                    stage: stage.clone(),
+                },
+                None => break,
+            },
+        };
+        if !visited.insert(pending.index) {
+            continue;
+        }
+        stage = pending.stage;
+
+        // Pages before this one belong to other labels, so only later ones
+        // may take a jump.
+        let first_page = pages.len();
+        let mut current_index = pending.index;
+        let mut next_labels = Vec::<String>::new();
+        let mut next_anchors = Vec::<String>::new();
+        // Labels and named menus name themselves below.
+        if !is_named(logical_lines.get(pending.index)) {
+            next_anchors.push(line_anchor(&logical_lines, pending.index));
+        }
+        // A `return`, or running off the end of the script, ends the game.
+        let mut ended = true;
+
+        // Choice bodies go first, so they follow their menu.
+        let mut enqueue = |index: Option<usize>, stage: &Stage, first: bool| {
+            if let Some(index) = index {
+                let pending = PendingLabel {
+                    index,
+                    stage: stage.clone(),
+                };
+                match first {
+                    true => queue.push_front(pending),
+                    false => queue.push_back(pending),
                 }
-                pages.push(Page {
-                    index: current_index,
-                    label: label,
-                    text: PageText::Dialogue {
-                        character_name: characters.get(character_key).unwrap().name.clone(),
-                        text: text.clone(),
-                    },
-                    character_images: on_screen_characters.clone(),
-                    background: current_background.clone(),
-                    unconditional_jump: None,
-                    end: false,
-                });
-                current_index += 1;
             }
-            ParseStatement::Menu {} => {
-                let mut label = None;
-                if next_has_label {
-                    label = Some(next_label.clone());
-                    next_has_label = false;
+        };
+
+        while current_index < logical_lines.len() {
+            if matches!(
+                logical_lines[current_index].statement,
+                ParseStatement::Choice { .. }
+            ) {
+                // Play ran off the end of a choice into the next one, so it
+                // carries on after the menu.
+                current_index = resume_index(&logical_lines, current_index);
+                continue;
+            }
+            if current_index != pending.index
+                && continuations.contains(&current_index)
+                && !is_named(logical_lines.get(current_index))
+            {
+                let anchor = line_anchor(&logical_lines, current_index);
+                if !visited.insert(current_index) {
+                    // Another choice already carried on past this menu.
+                    link_to(&mut pages, first_page, &anchor);
+                    alias_all(&mut aliases, &mut next_labels, &mut next_anchors, &anchor);
+                    ended = false;
+                    break;
                 }
-                let mut choices = Vec::<MenuChoice>::new();
-
-                let mut character_name = "".to_string();
-                let mut character_text: String = "".to_string();
-
-                current_index = current_index + 1;
-                loop {
-                    let line = &logical_lines[current_index];
-                    let statement = &line.statement;
-                    match statement {
-                        ParseStatement::Choice { text } => {
-                            choices.push(MenuChoice {
-                                text: text.clone(),
-                                jump_key: "".to_string(),
-                            });
-                            current_index += 1;
-                        }
-                        ParseStatement::Jump { key } => {
-                            choices.last_mut().unwrap().jump_key = key.clone();
-                            current_index += 1;
-                        }
-                        ParseStatement::Dialogue {
-                            character_key,
-                            text,
-                        } => {
-                            character_name = characters.get(character_key).unwrap().name.clone();
-                            character_text = text.clone();
-
-                            current_index += 1;
-                        }
-                        _ => {
+                next_anchors.push(anchor);
+            }
+
+            let line = &logical_lines[current_index];
+            let statement = &line.statement;
+            println!("Currently using: {:?}", statement);
+            println!("Currently showing: {:?}", stage);
+            match statement {
+                ParseStatement::Dialogue { speaker, text } => {
+                    pages.push(Page {
+                        line: line.line,
+                        labels: std::mem::take(&mut next_labels),
+                        anchors: std::mem::take(&mut next_anchors),
+                        text: PageText::Dialogue {
+                            character: speaker_character(speaker, &characters),
+                            text: text.clone(),
+                        },
+                        stage: stage.clone(),
+                        unconditional_jump: None,
+                        end: false,
+                    });
+                    current_index += 1;
+                }
+                ParseStatement::Menu { name } => {
+                    if let Some(name) = name {
+                        if current_index != pending.index && !visited.insert(current_index) {
+                            // Falling into a named menu that is already rendered.
+                            link_to(&mut pages, first_page, name);
+                            alias_all(&mut aliases, &mut next_labels, &mut next_anchors, name);
+                            ended = false;
                             break;
                         }
+                        next_labels.push(name.clone());
                     }
-                }
 
-                pages.push(Page {
-                    index: current_index,
-                    label: None,
-                    text: PageText::Menu {
-                        character_name: character_name,
-                        text: character_text,
-                        choices: choices,
-                    },
-                    character_images: [None, None, None, None, None],
-                    background: None,
-                    unconditional_jump: None,
-                    end: false,
-                });
-            }
-            ParseStatement::Label { key } => {
-                next_has_label = true;
-                next_label = key.clone();
+                    let menu_end = backend::block_end(&logical_lines, current_index);
+                    let after_menu = resume_index(&logical_lines, menu_end);
+                    let mut choices = Vec::<MenuChoice>::new();
+                    let mut bodies = Vec::<usize>::new();
 
-                current_index += 1;
-            }
-            ParseStatement::Jump { key } => {
-                if pages.len() > 0 {
-                    pages.last_mut().unwrap().unconditional_jump = Some(key.clone());
-                }
-                current_index += 1;
-            }
-            ParseStatement::End {} => {
-                pages.push(Page {
-                    index: current_index,
-                    label: None,
-                    text: PageText::Dialogue {
-                        character_name: "".to_string(),
-                        text: "End".to_string(),
-                    },
-                    character_images: on_screen_characters.clone(),
-                    background: None,
-                    unconditional_jump: None,
-                    end: true,
-                });
-                current_index += 1;
-            }
-            ParseStatement::Show { key, location } => {
-                for i in 0..on_screen_characters.len() {
-                    if let Some(character) = &on_screen_characters[i] {
-                        if character.split(' ').nth(0).unwrap() == key.split(' ').nth(0).unwrap() {
-                            on_screen_characters[i] = None;
+                    // A say statement before the choices is the caption,
+                    // shown along with them.
+                    let mut character = None;
+                    let mut character_text: String = "".to_string();
+
+                    current_index += 1;
+                    while current_index < menu_end {
+                        let line = &logical_lines[current_index];
+                        let choice_end = backend::block_end(&logical_lines, current_index);
+                        match &line.statement {
+                            ParseStatement::Choice { text, condition } => {
+                                // Each body is rendered on its own, like a
+                                // label, and one that only jumps links
+                                // straight to where it goes.
+                                let body = &logical_lines[current_index + 1..choice_end];
+                                let (jump_key, index) =
+                                    match body.first().map(|line| &line.statement) {
+                                        Some(ParseStatement::Jump { key }) => (key.clone(), None),
+                                        Some(_) => (
+                                            line_anchor(&logical_lines, current_index + 1),
+                                            Some(current_index + 1),
+                                        ),
+                                        None => (
+                                            line_anchor(&logical_lines, after_menu),
+                                            Some(after_menu),
+                                        ),
+                                    };
+                                match index {
+                                    Some(index) => bodies.push(index),
+                                    None => enqueue(
+                                        label_indices.get(jump_key.as_str()).copied(),
+                                        &stage,
+                                        false,
+                                    ),
+                                }
+                                choices.push(MenuChoice {
+                                    text: text.clone(),
+                                    jump_key,
+                                    condition: condition.clone(),
+                                });
+                            }
+                            ParseStatement::Dialogue { speaker, text } if choices.is_empty() => {
+                                character = speaker_character(speaker, &characters);
+                                character_text = text.clone();
+                            }
+                            _ => (),
                         }
+                        current_index = choice_end;
                     }
-                }
-                match location {
-                    Location::Left => {
-                        on_screen_characters[0] = Some(key.clone());
-                    }
-                    Location::CenterLeft => {
-                        on_screen_characters[1] = Some(key.clone());
+                    for index in bodies.into_iter().rev() {
+                        enqueue(Some(index), &stage, true);
                     }
-                    Location::Center => {
-                        on_screen_characters[2] = Some(key.clone());
-                    }
-                    Location::CenterRight => {
-                        on_screen_characters[3] = Some(key.clone());
+
+                    pages.push(Page {
+                        line: line.line,
+                        labels: std::mem::take(&mut next_labels),
+                        anchors: std::mem::take(&mut next_anchors),
+                        text: PageText::Menu {
+                            character,
+                            text: character_text,
+                            choices,
+                        },
+                        stage: stage.clone(),
+                        unconditional_jump: None,
+                        end: false,
+                    });
+                    // Play carries on through the choices.
+                    ended = false;
+                    break;
+                }
+                ParseStatement::Label { key } => {
+                    if current_index != pending.index && !visited.insert(current_index) {
+                        // Falling into a label that is already rendered.
+                        link_to(&mut pages, first_page, key);
+                        alias_all(&mut aliases, &mut next_labels, &mut next_anchors, key);
+                        ended = false;
+                        break;
                     }
-                    Location::Right => {
-                        on_screen_characters[4] = Some(key.clone());
+                    next_labels.push(key.clone());
+
+                    current_index += 1;
+                }
+                ParseStatement::Jump { key } => {
+                    link_to(&mut pages, first_page, key);
+                    alias_all(&mut aliases, &mut next_labels, &mut next_anchors, key);
+                    enqueue(label_indices.get(key.as_str()).copied(), &stage, false);
+                    ended = false;
+                    break;
+                }
+                ParseStatement::End {} => {
+                    break;
+                }
+                ParseStatement::Show {
+                    key,
+                    placement,
+                    zorder,
+                } => {
+                    let tag = key.split(' ').next().unwrap();
+                    let previous = stage
+                        .sprites
+                        .iter()
+                        .position(|sprite| sprite.tag() == tag)
+                        .map(|position| stage.sprites.remove(position));
+                    let xpos = placement
+                        .xpos
+                        .or(previous.as_ref().map(|sprite| sprite.xpos));
+                    let xanchor = placement
+                        .xanchor
+                        .or(previous.as_ref().map(|sprite| sprite.xanchor));
+                    // Ren'Py centres sprites shown without any position, and
+                    // otherwise defaults a missing xpos or xanchor to 0.
+                    let (xpos, xanchor) = match (xpos, xanchor) {
+                        (None, None) => (0.5, 0.5),
+                        (xpos, xanchor) => (xpos.unwrap_or(0.0), xanchor.unwrap_or(0.0)),
+                    };
+                    let sprite = Sprite {
+                        image: key.clone(),
+                        xpos,
+                        xanchor,
+                        zorder: zorder
+                            .or(previous.as_ref().map(|sprite| sprite.zorder))
+                            .unwrap_or(0),
+                        zoom: placement
+                            .zoom
+                            .or(previous.as_ref().map(|sprite| sprite.zoom))
+                            .unwrap_or(1.0),
+                    };
+                    // Sprites moved fully off screen are as good as hidden.
+                    if !sprite.is_off_screen() {
+                        stage.sprites.push(sprite);
                     }
-                    Location::Off => {}
+                    current_index += 1;
+                }
+                ParseStatement::Hide { key } => {
+                    let tag = key.split(' ').next().unwrap();
+                    stage.sprites.retain(|sprite| sprite.tag() != tag);
+                    current_index += 1;
+                }
+                ParseStatement::Scene { key } => {
+                    stage.background = Some(key.to_string());
+                    stage.sprites.clear();
+                    current_index += 1;
+                }
+                _ => {
+                    current_index += 1;
                 }
-                current_index += 1;
-            }
-            ParseStatement::Scene { key} => {
-                current_background = Some(key.to_string());
-                on_screen_characters = [None, None, None, None, None];
-                current_index += 1;
-            }
-            _ => {
-                current_index += 1;
             }
         }
-        if current_index == logical_lines.len() - 1 {
-            break;
+
+        if ended {
+            pages.push(Page {
+                line: logical_lines
+                    .get(current_index)
+                    .map_or(logical_lines.last().unwrap().line, |line| line.line),
+                labels: std::mem::take(&mut next_labels),
+                anchors: std::mem::take(&mut next_anchors),
+                text: PageText::Dialogue {
+                    character: None,
+                    text: "End".to_string(),
+                },
+                stage: stage.clone(),
+                unconditional_jump: None,
+                end: true,
+            });
         }
     }
 
-    pages
-}
-
-fn find_label_index(logical_lines: Vec<ParseLogicalLine>, key: String) -> usize {
-    for (index, line) in logical_lines.iter().enumerate() {
-        match &line.statement {
-            ParseStatement::Label { key: key_label } => {
-                if key == *key_label {
-                    return index;
+    // Resolve aliases once the labels they lead to have pages, following
+    // chains of them until no more can be placed.
+    loop {
+        let unresolved = aliases.len();
+        aliases.retain(|(alias, target)| {
+            let page = pages
+                .iter_mut()
+                .find(|page| page.labels.contains(target) || page.anchors.contains(target));
+            match (page, alias) {
+                (Some(page), Anchor::Label(label)) => {
+                    page.labels.push(label.clone());
+                    false
+                }
+                (Some(page), Anchor::Line(anchor)) => {
+                    page.anchors.push(anchor.clone());
+                    false
                 }
+                (None, _) => true,
             }
-            _ => (),
+        });
+        if aliases.len() == unresolved {
+            break;
         }
     }
-    return 0;
+
+    Ok(pages)
+}
+
+/// A name waiting for the page it leads to.
+enum Anchor {
+    Label(String),
+    Line(String),
 }
 
-fn latex_output(pages: Vec<Page>) -> String {
-    let mut output = String::new();
+/// Whether a line names itself: a label or a named menu.
+fn is_named(line: Option<&ParseLogicalLine>) -> bool {
+    matches!(
+        line.map(|line| &line.statement),
+        Some(ParseStatement::Label { .. } | ParseStatement::Menu { name: Some(_) })
+    )
+}
 
-    output += "\\documentclass[aspectratio=169]{beamer}\n\
-    \\usepackage{hyperref}\n\
-    \\beamertemplatenavigationsymbolsempty\n\
-    \\title{Game Title}\n\
-    \\author{Game Author}\n\
-    \\date{\\today}\n\
-    \\begin{document}\n\
-    \\frame{\\titlepage}\n\
-    ";
+/// The anchor for a page that starts at `index`, which may be just past
+/// the last line.
+fn line_anchor(lines: &[ParseLogicalLine], index: usize) -> String {
+    let line = match lines.get(index) {
+        Some(line) => line.line,
+        None => lines.last().map_or(1, |line| line.line + 1),
+    };
+    format!("line-{}", line)
+}
 
-    for (index, page_iter) in pages.iter().enumerate() {
-        let page = page_iter.clone();
-        output += "{\n";
-        if let Some(filename) = page.background {
-            if fs::exists(format!("output/images/{}.png", filename).as_str()).unwrap() {
-                output += format!("\\setbeamertemplate{{background}}{{\\includegraphics[width=\\paperwidth, height=\\paperheight]{{images/{}.png}}}}", filename).as_str();
-            }
-        }
-        output += "\\begin{frame}\n";
-        let label_add = if let Some(label) = page.label {
-            format!("\\phantomsection\\hypertarget{{{}}}\n", label).to_string()
-        } else {
-            "".to_string()
+/// Where play goes on from `index`: the line itself, unless it is a choice
+/// that play ran into from the end of the one before, in which case it is
+/// the first line after the menu, and so on out of any menus around it.
+fn resume_index(lines: &[ParseLogicalLine], mut index: usize) -> usize {
+    while index < lines.len() && matches!(lines[index].statement, ParseStatement::Choice { .. }) {
+        let indent = lines[index].indent;
+        index = match (0..index).rev().find(|line| lines[*line].indent < indent) {
+            Some(menu) => backend::block_end(lines, menu),
+            // A choice outside any menu: play skips its body.
+            None => backend::block_end(lines, index),
         };
-        let page_index_label = format!("\\phantomsection\\hypertarget{{pagenumber{}}}\n", index);
-        if page.character_images != [None, None, None, None, None] {
-            output += "\\begin{columns}\n";
-            for image in page.character_images {
-                if let Some(filename) = image {
-                    if fs::exists(format!("output/images/{}.png", filename).as_str()).unwrap() {
-                        output += "\\begin{column}{0.2\\textwidth}\n";
-                        output += format!(
-                            "\\includegraphics[width=\\textwidth]{{images/{}.png}}\n",
-                            filename
-                        )
-                        .as_str();
-                        output += "\\end{column}\n";
-                    }
-                } else {
-                    output += "\\begin{column}{0.2\\textwidth}\n";
-                    output += "\\end{column}\n";
-                }
-            }
-            output += "\\end{columns}\n";
-        }
-        match page.text.clone() {
-            PageText::Dialogue {
-                character_name,
-                text,
-            } => {
-                output += format!("\\frametitle{{{}}}\n", character_name).as_str();
-                output += &label_add;
-                output += &page_index_label;
-                output += format!("{}\n", escape_for_latex(text)).as_str();
+    }
+    index
+}
+
+/// Makes the last page rendered by this walk, if any, continue at `key`.
+fn link_to(pages: &mut [Page], first_page: usize, key: &str) {
+    if pages.len() > first_page {
+        pages.last_mut().unwrap().unconditional_jump = Some(key.to_string());
+    }
+}
+
+/// Records that the pending labels and anchors lead to `key`.
+fn alias_all(
+    aliases: &mut Vec<(Anchor, String)>,
+    labels: &mut Vec<String>,
+    anchors: &mut Vec<String>,
+    key: &str,
+) {
+    for label in labels.drain(..) {
+        aliases.push((Anchor::Label(label), key.to_string()));
+    }
+    for anchor in anchors.drain(..) {
+        aliases.push((Anchor::Line(anchor), key.to_string()));
+    }
+}
+
+/// Reports every say statement by a character key with no matching
+/// definition, or rejects the first in strict mode. This runs before any
+/// backend or report, so they all see the same warnings.
+fn check_speakers(lines: &[ParseLogicalLine], strict: bool) -> Result<(), String> {
+    let defined: HashSet<&str> = lines
+        .iter()
+        .filter_map(|line| match &line.statement {
+            ParseStatement::Definition { key, .. } => Some(key.as_str()),
+            _ => None,
+        })
+        .collect();
+    for line in lines {
+        if let ParseStatement::Dialogue {
+            speaker: Speaker::Character(key),
+            ..
+        } = &line.statement
+        {
+            if defined.contains(key.as_str()) {
+                continue;
             }
-            PageText::Menu {
-                character_name,
-                text,
-                choices,
-            } => {
-                output += format!("\\frametitle{{{}}}\n", character_name).as_str();
-                output += &label_add;
-                output += &page_index_label;
-                output += format!("{}\n", escape_for_latex(text)).as_str();
-                output += "\\begin{itemize}\n";
-                for choice in choices {
-                    output += format!(
-                        "\\item \\hyperlink{{{}}}{{{}}}\n",
-                        choice.jump_key,
-                        escape_for_latex(choice.text)
-                    )
-                    .as_str();
-                }
-                output += "\\end{itemize}\n";
+            if strict {
+                return Err(format!("line {}: unknown speaker `{}`", line.line, key));
             }
+            eprintln!("Warning: line {}: unknown speaker `{}`", line.line, key);
         }
-        output += "\\vfill{}\n";
-        output += "\\begin{flushright}\n";
-        if let Some(jump) = page.unconditional_jump {
-            output += format!("\\hyperlink{{{}}}{{\\beamergotobutton{{Next}}}}\n", jump).as_str();
-        } else if !(matches!(page.text, PageText::Menu { .. }) || page.end) {
-            output += format!(
-                "\\hyperlink{{pagenumber{}}}{{\\beamergotobutton{{Next}}}}\n",
-                index + 1
-            )
-            .as_str();
+    }
+    Ok(())
+}
+
+/// Resolves who a say statement is attributed to. A character key with no
+/// matching definition, already reported by `check_speakers`, is shown as
+/// the raw key.
+fn speaker_character(
+    speaker: &Speaker,
+    characters: &HashMap<String, Character>,
+) -> Option<Character> {
+    match speaker {
+        Speaker::Narrator => None,
+        Speaker::Character(key) => match characters.get(key) {
+            Some(character) if character.name.is_empty() => None,
+            Some(character) => Some(character.clone()),
+            None => Some(Character {
+                name: key.clone(),
+                color: "".to_string(),
+            }),
+        },
+        Speaker::Inline(name) => Some(Character {
+            name: name.clone(),
+            color: "".to_string(),
+        }),
+    }
+}
+
+/// Normalises a Ren'Py color such as `#c8ffc8`, `#cfc` or `#c8ffc8ff` to the
+/// six hex digits LaTeX's HTML color model and CSS both accept.
+fn html_color(color: &str) -> Option<String> {
+    let hex = color.trim().strip_prefix("#")?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let hex = match hex.len() {
+        3 | 4 => hex.chars().take(3).flat_map(|c| [c, c]).collect(),
+        6 | 8 => hex[..6].to_string(),
+        _ => return None,
+    };
+    Some(hex.to_uppercase())
+}
+
+/// Cuts the `with`, `behind`, `zorder`, `as` and `onlayer` clauses off part
+/// of a `show` statement.
+fn strip_show_clauses(text: &str) -> &str {
+    [" with ", " behind ", " zorder ", " as ", " onlayer "]
+        .iter()
+        .filter_map(|clause| text.find(clause))
+        .min()
+        .map_or(text, |end| &text[..end])
+}
+
+/// Splits a Python argument list on its top-level commas, leaving commas
+/// inside strings and nested calls alone.
+fn split_arguments(arguments: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in arguments.char_indices() {
+        match (quote, c) {
+            (Some(open), _) if c == open => quote = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(arguments[start..index].trim());
+                start = index + 1;
+            }
+            _ => (),
         }
-        output += "\\end{flushright}\n";
-        output += "\\end{frame}\n";
-        output += "}\n";
     }
+    parts.push(arguments[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Finds the value of `name=value` in a Python argument list.
+fn keyword_argument<'a>(arguments: &'a str, name: &str) -> Option<&'a str> {
+    split_arguments(arguments).into_iter().find_map(|argument| {
+        let (key, value) = argument.split_once("=")?;
+        (key.trim() == name).then(|| value.trim())
+    })
+}
 
-    output += "\\end{document}\n";
+/// Ren'Py's speaker for text that continues the previous say statement.
+const EXTEND_SPEAKER: &str = "extend";
 
-    output
+/// Statement keywords that can be followed by a string without being a say
+/// statement.
+const RENPY_KEYWORDS: [&str; 16] = [
+    "call", "define", "default", "hide", "if", "elif", "image", "init", "play", "python", "queue",
+    "renpy", "stop", "voice", "while", "with",
+];
+
+fn is_identifier(text: &str) -> bool {
+    text.chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_alphanumeric() || c == '_')
 }
 
-fn escape_for_latex(text: String) -> String {
-    if text.len() == 0 {
-        return "~".to_string();
+/// Splits the Ren'Py string literal at the start of `text` off the rest of the
+/// line, returning its raw contents and whatever follows the closing quote.
+fn take_string_literal(text: &str) -> Option<(String, &str)> {
+    let text = text.trim_start();
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let mut escaped = false;
+    for (index, c) in text.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return Some((text[1..index].to_string(), &text[index + 1..]));
+        }
     }
-    text.replace("$", "\\$")
-        .replace("%", "\\%")
-        .replace("#", "\\#")
-        .replace("_", "\\_")
+    None
 }
 
 fn clean_up_text(text: String) -> String {
     text.replace("\\\"", "\"").replace("\\n", "\n")
 }
+
+/// Parses a script the way `main` does with no options, for tests.
+#[cfg(test)]
+pub(crate) fn parse_test_script(script: &str) -> Vec<ParseLogicalLine> {
+    parse_script(
+        script,
+        &transform::default_transforms(),
+        transform::SCREEN_WIDTH,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> ParseStatement {
+        parse_line(
+            line.to_string(),
+            1,
+            &mut Vec::new(),
+            &transform::default_transforms(),
+            transform::SCREEN_WIDTH,
+        )
+        .unwrap()
+        .statement
+    }
+
+    fn traverse(script: &str) -> Vec<Page> {
+        traverse_game(parse_test_script(script)).unwrap()
+    }
+
+    /// Each page's text, with menus shown as their choices.
+    fn texts(pages: &[Page]) -> Vec<String> {
+        pages
+            .iter()
+            .map(|page| match &page.text {
+                PageText::Dialogue { text, .. } => text.clone(),
+                PageText::Menu { choices, .. } => {
+                    let choices: Vec<&str> =
+                        choices.iter().map(|choice| choice.text.as_str()).collect();
+                    format!("menu: {}", choices.join(", "))
+                }
+            })
+            .collect()
+    }
+
+    fn choices(page: &Page) -> &[MenuChoice] {
+        match &page.text {
+            PageText::Menu { choices, .. } => choices,
+            PageText::Dialogue { .. } => panic!("not a menu: {:?}", page),
+        }
+    }
+
+    #[test]
+    fn parses_narration_and_inline_speakers() {
+        assert!(matches!(
+            parse("    \"It was raining.\""),
+            ParseStatement::Dialogue { speaker: Speaker::Narrator, text } if text == "It was raining."
+        ));
+        assert!(matches!(
+            parse("    \"Stranger\" \"Who are you?\""),
+            ParseStatement::Dialogue { speaker: Speaker::Inline(name), text }
+                if name == "Stranger" && text == "Who are you?"
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_speakers_when_strict() {
+        let lines = parse_test_script(
+            "define e = Character(\"Eileen\")\nlabel start:\n    e \"Hi.\"\n    zz \"Who?\"\n",
+        );
+        assert_eq!(
+            check_speakers(&lines, true),
+            Err("line 4: unknown speaker `zz`".to_string())
+        );
+        assert_eq!(check_speakers(&lines, false), Ok(()));
+    }
+
+    #[test]
+    fn parses_show_placement() {
+        assert!(matches!(
+            parse("    show eileen happy at right zorder 2"),
+            ParseStatement::Show { key, placement, zorder: Some(2) }
+                if key == "eileen happy" && placement == Placement::align(1.0)
+        ));
+    }
+
+    #[test]
+    fn parses_menu_syntax() {
+        assert!(matches!(
+            parse("    menu chapter_choice:"),
+            ParseStatement::Menu { name: Some(name) } if name == "chapter_choice"
+        ));
+        assert!(matches!(
+            parse("    menu:"),
+            ParseStatement::Menu { name: None }
+        ));
+        assert!(matches!(
+            parse("        \"Kiss her\" if affection > 5:"),
+            ParseStatement::Choice { text, condition: Some(condition) }
+                if text == "Kiss her" && condition == "affection > 5"
+        ));
+        assert!(matches!(
+            parse("        set picked"),
+            ParseStatement::MenuSet { name } if name == "picked"
+        ));
+    }
+
+    #[test]
+    fn skips_transform_blocks() {
+        let lines = parse_script(
+            "transform t:\n    xalign 0.9\nlabel start:\n    show eileen at left\n    \"A\"\n",
+            &transform::default_transforms(),
+            transform::SCREEN_WIDTH,
+        );
+        assert_eq!(lines.len(), 3);
+        assert!(matches!(
+            &lines[1].statement,
+            ParseStatement::Show { placement, .. } if placement.xpos == Some(0.0)
+        ));
+    }
+
+    #[test]
+    fn extend_folds_into_the_line_before() {
+        let lines = parse_test_script(
+            "define e = Character(\"Eileen\")\nlabel start:\n    e \"Hello.\"\n    extend \" Again.\"\n    show eileen\n    extend \" Still here.\"\n",
+        );
+        assert_eq!(lines.len(), 5);
+        assert!(matches!(
+            &lines[2].statement,
+            ParseStatement::Dialogue { text, .. } if text == "Hello. Again."
+        ));
+        assert!(matches!(&lines[3].statement, ParseStatement::Show { .. }));
+        assert!(matches!(
+            &lines[4].statement,
+            ParseStatement::Dialogue { speaker: Speaker::Character(key), text }
+                if key == "e" && text == " Still here."
+        ));
+    }
+
+    #[test]
+    fn skips_screen_style_and_python_blocks() {
+        let lines = parse_test_script(
+            "init python:\n    x = \"a\"\nscreen hello():\n    text \"Hello\"\n    textbutton \"Go\" action Return()\nstyle say_label:\n    font \"DejaVuSans.ttf\"\nlabel start:\n    python:\n        name \"b\"\n    e \"Hi.\"\n",
+        );
+        assert_eq!(lines.len(), 2);
+        assert!(matches!(
+            &lines[1].statement,
+            ParseStatement::Dialogue { text, .. } if text == "Hi."
+        ));
+    }
+
+    #[test]
+    fn sprites_keep_their_own_positions() {
+        let pages = traverse(
+            "label start:\n    show a at left\n    show b:\n        xalign 0.1\n    show c at right zorder 3\n    \"A\"\n    show a happy\n    \"B\"\n",
+        );
+        let sprites = |page: &Page| -> Vec<(String, f32, i32)> {
+            page.stage
+                .sprites
+                .iter()
+                .map(|sprite| (sprite.image.clone(), sprite.xpos, sprite.zorder))
+                .collect()
+        };
+        assert_eq!(
+            sprites(&pages[0]),
+            [
+                ("a".to_string(), 0.0, 0),
+                ("b".to_string(), 0.1, 0),
+                ("c".to_string(), 1.0, 3)
+            ]
+        );
+        // Showing a new image for the same tag keeps its position.
+        assert_eq!(
+            sprites(&pages[1]),
+            [
+                ("b".to_string(), 0.1, 0),
+                ("c".to_string(), 1.0, 3),
+                ("a happy".to_string(), 0.0, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn xpos_keeps_the_anchor() {
+        let pages = traverse(
+            "label start:\n    show a:\n        xpos 0.5\n    show b:\n        xalign 0.5\n    show c at offscreenright\n    \"A\"\n",
+        );
+        let sprites: Vec<_> = pages[0]
+            .stage
+            .sprites
+            .iter()
+            .map(|sprite| (sprite.image.as_str(), sprite.xpos, sprite.xanchor))
+            .collect();
+        assert_eq!(sprites, [("a", 0.5, 0.0), ("b", 0.5, 0.5)]);
+        assert_eq!(pages[0].stage.sprites[0].left(0.2), 0.5);
+        assert_eq!(pages[0].stage.sprites[1].left(0.2), 0.4);
+    }
+
+    #[test]
+    fn choice_outside_a_menu_is_skipped() {
+        let pages = traverse("label start:\n\"Hi\":\n\"After\"\n");
+        assert_eq!(texts(&pages), ["After", "End"]);
+    }
+
+    #[test]
+    fn unreachable_labels_come_last() {
+        let pages = traverse(
+            "label before:\n    \"A\"\n    return\nlabel start:\n    \"B\"\n    return\nlabel after:\n    \"C\"\n    return\n",
+        );
+        assert_eq!(texts(&pages), ["B", "End", "A", "End", "C", "End"]);
+        assert_eq!(pages[2].labels, ["before"]);
+        assert_eq!(pages[4].labels, ["after"]);
+    }
+
+    #[test]
+    fn jump_cycle_links_back() {
+        let pages = traverse(
+            "label start:\n    \"A\"\n    jump other\nlabel other:\n    \"B\"\n    jump start\n",
+        );
+        assert_eq!(texts(&pages), ["A", "B"]);
+        assert_eq!(pages[1].labels, ["other"]);
+        assert_eq!(pages[1].unconditional_jump.as_deref(), Some("start"));
+    }
+
+    #[test]
+    fn menu_as_last_line() {
+        let pages = traverse("label start:\n    \"A\"\n    menu:\n");
+        assert_eq!(texts(&pages), ["A", "menu: "]);
+
+        let pages = traverse("label start:\n    menu:\n        \"Stay\":\n            \"B\"\n");
+        assert_eq!(texts(&pages), ["menu: Stay", "B", "End"]);
+        assert!(pages[2].end);
+    }
+
+    #[test]
+    fn label_followed_by_label_shares_a_page() {
+        let pages = traverse("label start:\nlabel second:\n    \"A\"\n");
+        assert_eq!(pages[0].labels, ["start", "second"]);
+        assert_eq!(texts(&pages), ["A", "End"]);
+    }
+
+    #[test]
+    fn labels_before_stage_changes_and_jumps_name_the_next_page() {
+        let pages = traverse(
+            "label start:\n    jump intro\nlabel intro:\n    scene bg room\n    show eileen\n    \"A\"\n    jump choose\nlabel choose:\nlabel again:\n    menu:\n        \"Again\":\n            jump again\n",
+        );
+        assert_eq!(texts(&pages), ["A", "menu: Again"]);
+        assert_eq!(pages[0].labels, ["intro", "start"]);
+        assert_eq!(pages[1].labels, ["choose", "again"]);
+        assert_eq!(pages[0].unconditional_jump.as_deref(), Some("choose"));
+    }
+
+    #[test]
+    fn menu_and_end_pages_keep_the_stage() {
+        let pages = traverse(
+            "label start:\n    scene bg room\n    show eileen at left\n    menu:\n        \"Stay\":\n            \"Fine.\"\n",
+        );
+        assert_eq!(texts(&pages), ["menu: Stay", "Fine.", "End"]);
+        for page in &pages {
+            assert_eq!(page.stage.background.as_deref(), Some("bg room"));
+            assert_eq!(page.stage.sprites.len(), 1);
+            assert_eq!(page.stage.sprites[0].image, "eileen");
+        }
+    }
+
+    #[test]
+    fn label_falls_into_menu() {
+        let pages = traverse(
+            "label start:\n    \"A\"\nlabel choose:\n    menu:\n        \"Again\":\n            jump start\n",
+        );
+        assert_eq!(texts(&pages), ["A", "menu: Again"]);
+        assert_eq!(pages[1].labels, ["choose"]);
+        assert_eq!(choices(&pages[1])[0].jump_key, "start");
+    }
+
+    #[test]
+    fn named_menu_reached_by_fall_through() {
+        let pages = traverse(
+            "label start:\n    \"A\"\n    menu pick:\n        \"Again\":\n            jump pick\n",
+        );
+        assert_eq!(texts(&pages), ["A", "menu: Again"]);
+        assert_eq!(pages[1].labels, ["pick"]);
+        assert_eq!(choices(&pages[1])[0].jump_key, "pick");
+    }
+
+    #[test]
+    fn choice_without_jump_continues_after_menu() {
+        let pages = traverse(
+            "label start:\n    menu:\n        \"Stay\":\n            \"Fine, stay.\"\n        \"Nothing\":\n            pass\n    \"After.\"\n",
+        );
+        assert_eq!(
+            texts(&pages),
+            ["menu: Stay, Nothing", "Fine, stay.", "After.", "End"]
+        );
+        let choices = choices(&pages[0]);
+        assert!(pages[1].anchors.contains(&choices[0].jump_key));
+        assert!(pages[2].anchors.contains(&choices[1].jump_key));
+    }
+}
@@ -0,0 +1,166 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{clean_up_text, take_string_literal};
+
+/// What the document says about the game, for title pages and the metadata
+/// of the formats that have it. Anything unknown is `None`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub date: Option<String>,
+    /// Image file shown behind the title on the cover page, when there is
+    /// a cover page.
+    pub cover: Option<String>,
+}
+
+impl Metadata {
+    /// Applies `other` on top of this metadata.
+    pub fn merge(self, other: Metadata) -> Metadata {
+        Metadata {
+            title: other.title.or(self.title),
+            author: other.author.or(self.author),
+            version: other.version.or(self.version),
+            date: other.date.or(self.date),
+            cover: other.cover.or(self.cover),
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or("Game Title")
+    }
+
+    pub fn author(&self) -> &str {
+        self.author.as_deref().unwrap_or("Game Author")
+    }
+}
+
+/// Files next to the script that Ren'Py projects keep these defines in.
+/// They are read before the script itself, so the script wins.
+const PROJECT_FILES: [&str; 2] = ["gui.rpy", "options.rpy"];
+
+/// Reads `define config.name`, `config.version` and
+/// `gui.main_menu_background` from the script and from the project files
+/// beside it. The background becomes the cover image, as a path relative
+/// to the current directory.
+pub fn collect_metadata(script: &str, input: &str) -> Metadata {
+    let directory = Path::new(input).parent().unwrap_or(Path::new(""));
+    let mut metadata = Metadata::default();
+    for file in PROJECT_FILES {
+        let path = directory.join(file);
+        if path == Path::new(input) {
+            continue;
+        }
+        if let Ok(contents) = std::fs::read_to_string(&path) {
+            metadata = metadata.merge(script_metadata(&contents, directory));
+        }
+    }
+    metadata.merge(script_metadata(script, directory))
+}
+
+fn script_metadata(script: &str, directory: &Path) -> Metadata {
+    let mut metadata = Metadata::default();
+    for line in script.lines() {
+        // Example line
+        // define config.name = _("The Question")
+        let Some((name, value)) = line
+            .trim()
+            .strip_prefix("define ")
+            .and_then(|rest| rest.split_once("="))
+        else {
+            continue;
+        };
+        let Some((value, _)) = value
+            .find(['"', '\''])
+            .and_then(|quote| take_string_literal(&value[quote..]))
+        else {
+            continue;
+        };
+        match name.trim() {
+            "config.name" => metadata.title = Some(strip_text_tags(&clean_up_text(value))),
+            "config.version" => metadata.version = Some(value),
+            // Paths are relative to the game directory, which the script is
+            // in. Other values, such as a colour, can't be a cover.
+            "gui.main_menu_background" if is_cover_image(&value) => {
+                metadata.cover = Some(directory.join(value).to_string_lossy().to_string());
+            }
+            _ => (),
+        }
+    }
+    metadata
+}
+
+/// Removes Ren'Py text tags such as `{b}`, which have no meaning in a
+/// document's title. `{{` is a literal brace.
+fn strip_text_tags(text: &str) -> String {
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        output += &rest[..open];
+        if rest[open..].starts_with("{{") {
+            output.push('{');
+            rest = &rest[open + 2..];
+            continue;
+        }
+        match rest[open..].find('}') {
+            Some(close) => rest = &rest[open + close + 1..],
+            None => {
+                rest = &rest[open..];
+                break;
+            }
+        }
+    }
+    output + rest
+}
+
+/// Whether a path names an image every backend can put on a cover page.
+pub fn is_cover_image(path: &str) -> bool {
+    let path = path.to_lowercase();
+    [".png", ".jpg", ".jpeg"]
+        .iter()
+        .any(|extension| path.ends_with(extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_defines_from_the_script() {
+        let metadata = script_metadata(
+            "define config.name = _(\"The {b}Question{/b}\")\ndefine config.version = \"1.0\"\ndefine gui.main_menu_background = \"gui/main_menu.png\"\n",
+            Path::new("game"),
+        );
+        assert_eq!(metadata.title.as_deref(), Some("The Question"));
+        assert_eq!(metadata.version.as_deref(), Some("1.0"));
+        assert_eq!(metadata.cover.as_deref(), Some("game/gui/main_menu.png"));
+
+        let metadata = script_metadata(
+            "define gui.main_menu_background = \"#000\"\n",
+            Path::new("game"),
+        );
+        assert_eq!(metadata.cover, None);
+    }
+
+    #[test]
+    fn overrides_win() {
+        let script = Metadata {
+            title: Some("Script".to_string()),
+            version: Some("1.0".to_string()),
+            ..Metadata::default()
+        };
+        let options = Metadata {
+            title: Some("Command line".to_string()),
+            author: Some("Author".to_string()),
+            ..Metadata::default()
+        };
+        let metadata = script.merge(options);
+        assert_eq!(metadata.title(), "Command line");
+        assert_eq!(metadata.author(), "Author");
+        assert_eq!(metadata.version.as_deref(), Some("1.0"));
+        assert_eq!(Metadata::default().title(), "Game Title");
+    }
+}
@@ -0,0 +1,77 @@
+use crate::graph::{EdgeKind, FlowGraph, Target};
+use crate::ParseLogicalLine;
+
+/// How many paths are listed before giving up.
+const PATH_LIMIT: usize = 1000;
+
+/// Lists every playthrough from `start` to a `return` with the choices
+/// taken on the way, as a test matrix for QA.
+pub fn report(lines: &[ParseLogicalLine]) -> String {
+    let graph = FlowGraph::build(lines);
+    let mut output = String::new();
+    let (paths, truncated) = graph.ending_paths(PATH_LIMIT);
+    let mut endings = Vec::new();
+    for (number, path) in paths.iter().enumerate() {
+        // Paths end on the edge into their `return`.
+        let Target::Node(ending) = graph.edges[*path.last().unwrap()].to else {
+            unreachable!()
+        };
+        if !endings.contains(&ending) {
+            endings.push(ending);
+        }
+        output += format!(
+            "Path {}: ending at line {} in `{}`\n",
+            number + 1,
+            graph.nodes[ending].line,
+            graph.label_of(ending).unwrap_or_default()
+        )
+        .as_str();
+        let mut choices = 0;
+        for edge in path {
+            let edge = &graph.edges[*edge];
+            if let EdgeKind::Choice { text, condition } = &edge.kind {
+                output += format!("    line {}: {}", edge.line, text).as_str();
+                if let Some(condition) = condition {
+                    output += format!(" (if {})", condition).as_str();
+                }
+                output += "\n";
+                choices += 1;
+            }
+        }
+        if choices == 0 {
+            output += "    (no choices)\n";
+        }
+    }
+    if truncated && paths.is_empty() {
+        output += "No path found before the search stopped\n";
+    } else if truncated {
+        output += format!("Stopped after {} paths\n", paths.len()).as_str();
+    }
+    output += format!("{} path(s) to {} ending(s)\n", paths.len(), endings.len()).as_str();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_choices_and_endings() {
+        let lines = crate::parse_test_script(
+            "label start:\n    menu:\n        \"Left\" if brave:\n            jump left\n        \"Right\":\n            return\nlabel left:\n    return\n",
+        );
+        assert_eq!(
+            report(&lines),
+            "Path 1: ending at line 8 in `left`\n    line 3: Left (if brave)\nPath 2: ending at line 6 in `start`\n    line 5: Right\n2 path(s) to 2 ending(s)\n"
+        );
+    }
+
+    #[test]
+    fn lists_paths_without_choices() {
+        let lines = crate::parse_test_script("label start:\n    \"A\"\n    return\n");
+        assert_eq!(
+            report(&lines),
+            "Path 1: ending at line 3 in `start`\n    (no choices)\n1 path(s) to 1 ending(s)\n"
+        );
+    }
+}
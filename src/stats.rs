@@ -0,0 +1,287 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashSet, VecDeque};
+
+use crate::backend::{character_names, label_bodies, speaker_name};
+use crate::graph::{EdgeKind, FlowEdge, FlowGraph, NodeKind, Target};
+use crate::{ParseLogicalLine, ParseStatement};
+
+/// How many playthroughs are counted and compared to find the longest.
+const PATH_LIMIT: usize = 1000;
+
+/// Say statements and the words in them.
+#[derive(Default, Clone, Copy)]
+struct Count {
+    lines: usize,
+    words: usize,
+}
+
+impl Count {
+    fn add(&mut self, text: &str) {
+        self.lines += 1;
+        self.words += text.split_whitespace().count();
+    }
+}
+
+/// Counts for budgeting voice acting and translation: words and lines per
+/// character, label and route, the size of the menus, and how long a
+/// playthrough can be.
+pub fn report(lines: &[ParseLogicalLine]) -> String {
+    let mut output = String::new();
+    let text_of = |index: usize| match &lines[index].statement {
+        ParseStatement::Dialogue { text, .. } => text.as_str(),
+        _ => "",
+    };
+
+    let names = character_names(lines);
+    let mut characters = Vec::<(&str, Count)>::new();
+    let mut total = Count::default();
+    for line in lines {
+        if let ParseStatement::Dialogue { speaker, text } = &line.statement {
+            let name = speaker_name(speaker, &names).unwrap_or("(narrator)");
+            match characters.iter_mut().find(|(known, _)| *known == name) {
+                Some((_, count)) => count.add(text),
+                None => {
+                    let mut count = Count::default();
+                    count.add(text);
+                    characters.push((name, count));
+                }
+            }
+            total.add(text);
+        }
+    }
+    characters.sort_by_key(|(_, count)| Reverse(count.words));
+    output += "Characters:\n";
+    for (name, count) in &characters {
+        output += count_row(name, *count).as_str();
+    }
+    output += count_row("total", total).as_str();
+
+    output += "\nLabels:\n";
+    for (label, body) in label_bodies(lines) {
+        let mut count = Count::default();
+        for line in body {
+            if let ParseStatement::Dialogue { text, .. } = &line.statement {
+                count.add(text);
+            }
+        }
+        output += count_row(label, count).as_str();
+    }
+
+    let graph = FlowGraph::build(lines);
+    let played = played_nodes(&graph);
+    let endings: Vec<usize> = (0..graph.nodes.len())
+        .filter(|node| played[*node] && graph.nodes[*node].kind == NodeKind::End)
+        .collect();
+    output += "\nRoutes:\n";
+    for ending in &endings {
+        let mut count = Count::default();
+        for index in route_dialogue(&graph, &played, *ending) {
+            count.add(text_of(index));
+        }
+        let name = format!(
+            "ending at line {} ({})",
+            graph.nodes[*ending].line,
+            graph.label_of(*ending).unwrap_or_default()
+        );
+        output += count_row(&name, count).as_str();
+    }
+
+    let menus = lines
+        .iter()
+        .filter(|line| matches!(line.statement, ParseStatement::Menu { .. }))
+        .count();
+    let choices = lines
+        .iter()
+        .filter(|line| matches!(line.statement, ParseStatement::Choice { .. }))
+        .count();
+    output += format!("\nMenus: {}\nChoices: {}\n", menus, choices).as_str();
+
+    let edge_counts: Vec<Count> = graph
+        .edges
+        .iter()
+        .map(|edge| {
+            let mut count = Count::default();
+            for index in &edge.dialogue {
+                count.add(text_of(*index));
+            }
+            count
+        })
+        .collect();
+    let path_count = |path: &Vec<usize>| {
+        let mut count = Count::default();
+        for edge in path {
+            count.words += edge_counts[*edge].words;
+            count.lines += edge_counts[*edge].lines;
+        }
+        count
+    };
+    let (paths, truncated) = graph.ending_paths(PATH_LIMIT);
+    let longest = paths
+        .iter()
+        .max_by_key(|path| {
+            let count = path_count(path);
+            (count.words, count.lines)
+        })
+        .map(|path| (path_count(path), path.clone()));
+    let shortest = shortest_path(&graph, &edge_counts).map(|path| (path_count(&path), path));
+    for (name, path) in [("Longest", longest), ("Shortest", shortest)] {
+        match path {
+            Some((count, path)) => {
+                output += format!(
+                    "{} path to a return: {} lines, {} words ({})\n",
+                    name,
+                    count.lines,
+                    count.words,
+                    path_labels(&graph, &path).join(" -> ")
+                )
+                .as_str()
+            }
+            None => output += format!("{} path to a return: none\n", name).as_str(),
+        }
+    }
+    if truncated && paths.is_empty() {
+        output += "(no path found before the search stopped)\n";
+    } else if truncated {
+        output += format!(
+            "(only the first {} paths were compared for the longest)\n",
+            paths.len()
+        )
+        .as_str();
+    }
+    output += format!("Endings: {}\n", endings.len()).as_str();
+
+    output
+}
+
+fn count_row(name: &str, count: Count) -> String {
+    format!(
+        "  {:<32} {:>6} lines {:>8} words\n",
+        name, count.lines, count.words
+    )
+}
+
+/// The nodes play can get to from `start` without following a `call`, so a
+/// called label's `return`, which goes back to the caller, isn't an ending.
+fn played_nodes(graph: &FlowGraph) -> Vec<bool> {
+    let mut seen = vec![false; graph.nodes.len()];
+    let mut queue: VecDeque<usize> = graph.start().into_iter().collect();
+    while let Some(node) = queue.pop_front() {
+        if seen[node] {
+            continue;
+        }
+        seen[node] = true;
+        for edge in graph.edges.iter().filter(|edge| edge.from == node) {
+            match edge.to {
+                Target::Node(to) if edge.kind != EdgeKind::Call => queue.push_back(to),
+                _ => (),
+            }
+        }
+    }
+    seen
+}
+
+/// Indices of the say statements that can be read on the way from `start`
+/// to `ending`: those on edges that some path between the two takes.
+fn route_dialogue(graph: &FlowGraph, played: &[bool], ending: usize) -> BTreeSet<usize> {
+    let followed = |edge: &&FlowEdge| edge.kind != EdgeKind::Call;
+    let mut to_ending = HashSet::new();
+    let mut queue = VecDeque::from([ending]);
+    while let Some(node) = queue.pop_front() {
+        if to_ending.insert(node) {
+            for edge in graph.edges.iter().filter(followed) {
+                if edge.to == Target::Node(node) {
+                    queue.push_back(edge.from);
+                }
+            }
+        }
+    }
+
+    let mut dialogue = BTreeSet::new();
+    for edge in graph.edges.iter().filter(followed) {
+        if let Target::Node(to) = edge.to {
+            if played[edge.from] && to_ending.contains(&to) {
+                dialogue.extend(&edge.dialogue);
+            }
+        }
+    }
+    dialogue
+}
+
+/// The way from `start` to a `return` with the fewest words, then lines, as
+/// the edges taken. A breadth-first search that always carries on from the
+/// nearest node, so unlike listing the paths it can't miss the shortest on
+/// a branchy script.
+fn shortest_path(graph: &FlowGraph, edge_counts: &[Count]) -> Option<Vec<usize>> {
+    let start = graph.start()?;
+    let mut outgoing = vec![Vec::new(); graph.nodes.len()];
+    for (index, edge) in graph.edges.iter().enumerate() {
+        if let Target::Node(to) = edge.to {
+            if edge.kind != EdgeKind::Call {
+                outgoing[edge.from].push((index, to));
+            }
+        }
+    }
+    let mut nearest = vec![None; graph.nodes.len()];
+    // The edge each node was reached along, to walk the path back.
+    let mut reached_by: Vec<Option<usize>> = vec![None; graph.nodes.len()];
+    nearest[start] = Some((0, 0));
+    let mut queue = BinaryHeap::from([Reverse(((0, 0), start))]);
+    while let Some(Reverse((distance, node))) = queue.pop() {
+        if nearest[node].is_some_and(|nearest| nearest < distance) {
+            continue;
+        }
+        if graph.nodes[node].kind == NodeKind::End {
+            let mut path = Vec::new();
+            let mut at = node;
+            while let Some(edge) = reached_by[at] {
+                path.push(edge);
+                at = graph.edges[edge].from;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for (edge, to) in &outgoing[node] {
+            let count = edge_counts[*edge];
+            let distance = (distance.0 + count.words, distance.1 + count.lines);
+            if nearest[*to].is_none_or(|nearest| distance < nearest) {
+                nearest[*to] = Some(distance);
+                reached_by[*to] = Some(*edge);
+                queue.push(Reverse((distance, *to)));
+            }
+        }
+    }
+    None
+}
+
+/// The labels a path passes through, in order.
+fn path_labels<'a>(graph: &'a FlowGraph, path: &[usize]) -> Vec<&'a str> {
+    let mut labels = vec!["start"];
+    for edge in path {
+        if let Target::Node(to) = graph.edges[*edge].to {
+            if let NodeKind::Label { key } = &graph.nodes[to].kind {
+                labels.push(key);
+            }
+        }
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_shortest_path_past_the_path_limit() {
+        // Eleven menus of two choices one after another make 2048 paths,
+        // more than are listed, and the last choice of each is the short one.
+        let mut script = "label start:\n".to_string();
+        for _ in 0..11 {
+            script += "    menu:\n        \"Long\":\n            \"One two three.\"\n        \"Short\":\n            pass\n";
+        }
+        script += "    \"Done.\"\n    return\n";
+        let report = report(&crate::parse_test_script(&script));
+        assert!(report.contains("Longest path to a return: 12 lines, 34 words (start)\n"));
+        assert!(report.contains("Shortest path to a return: 1 lines, 1 words (start)\n"));
+        assert!(report.contains("(only the first 1000 paths were compared for the longest)\n"));
+    }
+}
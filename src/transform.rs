@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{keyword_argument, split_arguments};
+
+/// Width in pixels that integer `xpos` values are measured against, unless
+/// `screen_width` in `rentex.toml` says otherwise.
+pub const SCREEN_WIDTH: f32 = 1920.0;
+
+/// Where a transform puts a sprite. Properties the transform leaves alone
+/// are `None`, so the sprite keeps whatever it had before.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Placement {
+    /// Where the sprite's anchor goes, as a fraction of the screen width.
+    pub xpos: Option<f32>,
+    /// Which point of the sprite goes at `xpos`, as a fraction of its width.
+    pub xanchor: Option<f32>,
+    pub zoom: Option<f32>,
+}
+
+impl Placement {
+    /// What `xalign` sets: the same fraction of the sprite and the screen
+    /// lined up.
+    pub fn align(xalign: f32) -> Placement {
+        Placement {
+            xpos: Some(xalign),
+            xanchor: Some(xalign),
+            zoom: None,
+        }
+    }
+
+    /// Applies `other` on top of this placement.
+    pub fn merge(self, other: Placement) -> Placement {
+        Placement {
+            xpos: other.xpos.or(self.xpos),
+            xanchor: other.xanchor.or(self.xanchor),
+            zoom: other.zoom.or(self.zoom),
+        }
+    }
+
+    /// Sets the position property `name` to `value`, a fraction of the
+    /// screen. Returns whether `name` is one.
+    fn set(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "xalign" | "align" => *self = self.merge(Placement::align(value)),
+            "xpos" | "pos" => self.xpos = Some(value),
+            "xanchor" | "anchor" => self.xanchor = Some(value),
+            "xcenter" | "xycenter" => {
+                self.xpos = Some(value);
+                self.xanchor = Some(0.5);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Placement::default()
+    }
+}
+
+/// Maps transform names to the placement a sprite shown with them ends up
+/// with.
+pub type TransformTable = HashMap<String, Placement>;
+
+/// Ren'Py's built-in position transforms, as their xpos and xanchor.
+pub fn default_transforms() -> TransformTable {
+    [
+        ("left", 0.0, 0.0),
+        ("topleft", 0.0, 0.0),
+        ("center", 0.5, 0.5),
+        ("truecenter", 0.5, 0.5),
+        ("top", 0.5, 0.5),
+        ("right", 1.0, 1.0),
+        ("topright", 1.0, 1.0),
+        ("offscreenleft", 0.0, 1.0),
+        ("offscreenright", 1.0, 0.0),
+    ]
+    .iter()
+    .map(|(name, xpos, xanchor)| {
+        let placement = Placement {
+            xpos: Some(*xpos),
+            xanchor: Some(*xanchor),
+            zoom: None,
+        };
+        (name.to_string(), placement)
+    })
+    .collect()
+}
+
+/// Adds every `transform name:` block and `define name = Position(...)` or
+/// `Transform(...)` in the script to the table. Ren'Py defines these at init
+/// time, so this runs over the whole script before any `show` is parsed.
+pub fn collect_transforms(script: &str, transforms: &mut TransformTable, screen_width: f32) {
+    let mut current: Option<(String, usize)> = None;
+    for line in script.lines() {
+        let line_trim = line.trim();
+        if line_trim.is_empty() || line_trim.starts_with("#") {
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+        if let Some((name, transform_indent)) = &current {
+            if indent > *transform_indent {
+                if let Some(placement) =
+                    stage_direction_placement(line_trim, transforms, screen_width)
+                {
+                    let merged = transforms
+                        .get(name)
+                        .copied()
+                        .unwrap_or_default()
+                        .merge(placement);
+                    transforms.insert(name.clone(), merged);
+                }
+                continue;
+            }
+            current = None;
+        }
+        if let Some(rest) = line_trim.strip_prefix("transform ") {
+            let name = rest.split(['(', ':']).next().unwrap().trim().to_string();
+            transforms.remove(&name);
+            current = Some((name, indent));
+        } else if let Some(rest) = line_trim.strip_prefix("define ") {
+            if let Some((name, value)) = rest.split_once("=") {
+                let placement = transform_placement(value, transforms, screen_width);
+                if !placement.is_empty() {
+                    transforms.insert(name.trim().to_string(), placement);
+                }
+            }
+        }
+    }
+}
+
+/// Resolves the expression after `show ... at`, e.g. `left`,
+/// `Transform(xalign=0.3, zoom=0.5)` or `leftstage, flipped`. Later
+/// transforms in the list override earlier ones.
+pub fn transform_placement(
+    expression: &str,
+    transforms: &TransformTable,
+    screen_width: f32,
+) -> Placement {
+    let mut placement = Placement::default();
+    for transform in split_arguments(expression.trim()) {
+        let (name, arguments) = match transform.find("(") {
+            Some(open) => {
+                let close = transform.rfind(")").unwrap_or(transform.len());
+                (
+                    &transform[..open],
+                    &transform[open + 1..close.max(open + 1)],
+                )
+            }
+            None => (transform, ""),
+        };
+        placement = placement.merge(transforms.get(name.trim()).copied().unwrap_or_default());
+        for key in ["xalign", "xpos", "xanchor", "xcenter"] {
+            if let Some(value) = keyword_argument(arguments, key)
+                .and_then(|value| parse_position(value, screen_width))
+            {
+                placement.set(key, value);
+            }
+        }
+        if let Some(zoom) =
+            keyword_argument(arguments, "zoom").and_then(|zoom| zoom.parse::<f32>().ok())
+        {
+            placement.zoom = Some(zoom);
+        }
+    }
+    placement
+}
+
+/// Placement set by a line inside a `show` or `transform` block, either a
+/// transform name on its own or ATL such as `xalign 0.3`, `zoom 0.8` or
+/// `linear 0.5 xpos 200`.
+pub fn stage_direction_placement(
+    line: &str,
+    transforms: &TransformTable,
+    screen_width: f32,
+) -> Option<Placement> {
+    if line.contains("\"") {
+        return None;
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let mut placement = words
+        .first()
+        .and_then(|word| transforms.get(word.trim_end_matches(":")))
+        .copied()
+        .unwrap_or_default();
+    for (index, word) in words.iter().enumerate() {
+        let next = words
+            .get(index + 1)
+            .map(|value| value.trim_end_matches([',', ')', ':']));
+        match *word {
+            // `align`, `pos`, `anchor` and `xycenter` take an (x, y) pair.
+            "xalign" | "xpos" | "xanchor" | "xcenter" | "align" | "pos" | "anchor" | "xycenter" => {
+                let next = next.map(|value| value.trim_start_matches("("));
+                if let Some(value) = next.and_then(|value| parse_position(value, screen_width)) {
+                    placement.set(word, value);
+                }
+            }
+            "zoom" => {
+                if let Some(zoom) = next.and_then(|zoom| zoom.parse::<f32>().ok()) {
+                    placement.zoom = Some(zoom);
+                }
+            }
+            _ => (),
+        }
+    }
+    (!placement.is_empty()).then_some(placement)
+}
+
+/// Parses a position value. Floats are fractions of the screen and integers
+/// are pixels, as in Ren'Py.
+fn parse_position(value: &str, screen_width: f32) -> Option<f32> {
+    let value = value.trim();
+    if let Some(pixels) = value.strip_prefix("absolute(") {
+        return pixels
+            .trim_end_matches(")")
+            .parse::<f32>()
+            .ok()
+            .map(|pixels| pixels / screen_width);
+    }
+    if value.contains(".") {
+        value.parse::<f32>().ok()
+    } else {
+        value
+            .parse::<i32>()
+            .ok()
+            .map(|pixels| pixels as f32 / screen_width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placement(expression: &str) -> Placement {
+        transform_placement(expression, &default_transforms(), SCREEN_WIDTH)
+    }
+
+    #[test]
+    fn keeps_position_and_anchor_apart() {
+        assert_eq!(placement("Transform(xalign=0.5)"), Placement::align(0.5));
+        assert_eq!(
+            placement("Transform(xpos=0.5)"),
+            Placement {
+                xpos: Some(0.5),
+                ..Placement::default()
+            }
+        );
+        assert_eq!(
+            placement("Position(xcenter=960)"),
+            Placement {
+                xpos: Some(0.5),
+                xanchor: Some(0.5),
+                zoom: None,
+            }
+        );
+        assert_eq!(
+            placement("right, Transform(xpos=0.25)"),
+            Placement {
+                xpos: Some(0.25),
+                xanchor: Some(1.0),
+                zoom: None,
+            }
+        );
+    }
+
+    #[test]
+    fn reads_atl_positions() {
+        let direction =
+            |line: &str| stage_direction_placement(line, &default_transforms(), SCREEN_WIDTH);
+        assert_eq!(
+            direction("linear 0.5 xpos 480"),
+            Some(Placement {
+                xpos: Some(0.25),
+                ..Placement::default()
+            })
+        );
+        assert_eq!(
+            direction("anchor (0.5, 1.0) pos (0.2, 1.0)"),
+            Some(Placement {
+                xpos: Some(0.2),
+                xanchor: Some(0.5),
+                zoom: None,
+            })
+        );
+        assert_eq!(direction("\"Hello\""), None);
+    }
+}